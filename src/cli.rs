@@ -3,37 +3,160 @@ use std::env;
 use std::ffi::{OsStr, OsString};
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::Duration;
 
-use feedlynx::{DEFAULT_ADDR, DEFAULT_PORT};
+use feedlynx::webpage::{
+    DEFAULT_FETCH_TIMEOUT, DEFAULT_MAX_PAGE_SIZE, DEFAULT_MAX_REDIRECTS, DEFAULT_MAX_SUMMARY,
+    DEFAULT_MIN_TITLE_LENGTH,
+};
+use feedlynx::{DEFAULT_ADDR, DEFAULT_CACHE_MAX_AGE, DEFAULT_PORT, DEFAULT_READ_TIMEOUT};
 use pico_args::Arguments;
 
+/// The parsed command line arguments
+pub struct Args {
+    pub command: Command,
+    /// The log level selected by `--quiet` or `--verbose`, if any
+    pub log_level: Option<&'static str>,
+}
+
 pub enum Command {
-    Serve(PathBuf),
+    Serve {
+        feed_path: PathBuf,
+        /// The address to listen on from `--listen`, optionally with a port
+        listen: Option<String>,
+        /// The port to listen on from `--port`
+        port: Option<u16>,
+    },
     GenToken,
-    Fetch(Option<OsString>),
+    /// Fetch a URL and print its metadata
+    Fetch {
+        url: Option<OsString>,
+        /// The time allowed for each request from `--timeout`
+        timeout: Option<Duration>,
+        /// The number of redirects to follow from `--max-redirects`
+        max_redirects: Option<usize>,
+    },
+    Check(PathBuf),
+    Trim(PathBuf),
+    Import(PathBuf, PathBuf),
     Exit(ExitCode),
 }
 
-pub fn parse_args() -> Result<Command, pico_args::Error> {
+pub fn parse_args() -> Result<Args, pico_args::Error> {
     let mut pargs = Arguments::from_env();
     if pargs.contains(["-V", "--version"]) {
-        return print_version();
+        return print_version().map(Args::from);
     } else if pargs.contains(["-h", "--help"]) {
-        return print_help();
+        return print_help().map(Args::from);
+    }
+
+    // Flags are extracted before the free arguments so that they can appear anywhere
+    let mut verbosity = 0;
+    if pargs.contains("-vv") {
+        verbosity += 2;
     }
+    while pargs.contains(["-v", "--verbose"]) {
+        verbosity += 1;
+    }
+    let log_level = match (pargs.contains(["-q", "--quiet"]), verbosity) {
+        (true, _) => Some("error"),
+        (false, 0) => None,
+        (false, 1) => Some("debug"),
+        (false, _) => Some("trace"),
+    };
+
+    Ok(Args {
+        command: parse_command(&mut pargs)?,
+        log_level,
+    })
+}
 
+fn parse_command(pargs: &mut Arguments) -> Result<Command, pico_args::Error> {
+    let listen = pargs.opt_value_from_str(["-l", "--listen"])?;
+    let port = pargs.opt_value_from_str(["-p", "--port"])?;
     let arg0 = pargs.opt_free_from_os_str(osstring)?;
     match arg0 {
         Some(arg) if arg == "gen-token" => Ok(Command::GenToken),
-        Some(arg) if arg == "fetch" => Ok(Command::Fetch(pargs.opt_free_from_os_str(osstring)?)),
-        Some(arg) => Ok(Command::Serve(PathBuf::from(arg))),
+        Some(arg) if arg == "serve" => Ok(feed_path_arg(pargs, "serve")?.map_or(
+            Command::Exit(ExitCode::FAILURE),
+            |feed_path| Command::Serve {
+                feed_path,
+                listen,
+                port,
+            },
+        )),
+        Some(arg) if arg == "fetch" => {
+            let timeout = pargs
+                .opt_value_from_str::<_, u64>("--timeout")?
+                .map(Duration::from_secs);
+            let max_redirects = pargs.opt_value_from_str("--max-redirects")?;
+            Ok(Command::Fetch {
+                url: pargs.opt_free_from_os_str(osstring)?,
+                timeout,
+                max_redirects,
+            })
+        }
+        Some(arg) if arg == "check" => {
+            Ok(feed_path_arg(pargs, "check")?
+                .map_or(Command::Exit(ExitCode::FAILURE), Command::Check))
+        }
+        Some(arg) if arg == "trim" => {
+            Ok(feed_path_arg(pargs, "trim")?
+                .map_or(Command::Exit(ExitCode::FAILURE), Command::Trim))
+        }
+        Some(arg) if arg == "import" => {
+            let feed_path = pargs.opt_free_from_os_str(osstring)?.map(PathBuf::from);
+            let urls_path = pargs.opt_free_from_os_str(osstring)?.map(PathBuf::from);
+            match (feed_path, urls_path) {
+                (Some(feed_path), Some(urls_path)) => Ok(Command::Import(feed_path, urls_path)),
+                _ => {
+                    eprintln!(
+                        "Usage: {} import path/to/feed.xml path/to/urls.txt",
+                        env!("CARGO_BIN_NAME")
+                    );
+                    Ok(Command::Exit(ExitCode::FAILURE))
+                }
+            }
+        }
+        // A bare feed path is served too, as it was before the serve command was added. Use
+        // `serve` for a feed whose path is the name of a command.
+        Some(arg) => Ok(Command::Serve {
+            feed_path: PathBuf::from(arg),
+            listen,
+            port,
+        }),
         None => {
-            eprintln!("Usage: {} path/to/feed.xml", env!("CARGO_BIN_NAME"));
+            eprintln!("Usage: {} serve path/to/feed.xml", env!("CARGO_BIN_NAME"));
             Ok(Command::Exit(ExitCode::FAILURE))
         }
     }
 }
 
+/// Parse the feed path argument of a subcommand, printing usage if it's missing
+fn feed_path_arg(
+    pargs: &mut Arguments,
+    subcommand: &str,
+) -> Result<Option<PathBuf>, pico_args::Error> {
+    let path = pargs.opt_free_from_os_str(osstring)?.map(PathBuf::from);
+    if path.is_none() {
+        eprintln!(
+            "Usage: {} {} path/to/feed.xml",
+            env!("CARGO_BIN_NAME"),
+            subcommand
+        );
+    }
+    Ok(path)
+}
+
+impl From<Command> for Args {
+    fn from(command: Command) -> Self {
+        Args {
+            command,
+            log_level: None,
+        }
+    }
+}
+
 fn osstring(s: &OsStr) -> Result<OsString, Infallible> {
     Ok(s.to_os_string())
 }
@@ -50,7 +173,32 @@ pub fn print_help() -> Result<Command, pico_args::Error> {
 {bin} collects links to read or watch later in an RSS feed.
 
 USAGE:
+    {bin} serve [OPTIONS] FEED_PATH
     {bin} [OPTIONS] FEED_PATH
+    {bin} check FEED_PATH
+    {bin} trim FEED_PATH
+    {bin} import FEED_PATH URLS_FILE
+    {bin} fetch [--timeout SECS] [--max-redirects N] URL
+
+COMMANDS:
+    serve
+            Serve the feed at FEED_PATH, creating it if it doesn't exist. This
+            is the default when FEED_PATH is given without a command
+
+    check
+            Check that the feed at FEED_PATH can be read and exit
+
+    trim
+            Remove old entries from the feed at FEED_PATH and exit
+
+    import
+            Add the links in URLS_FILE, one per line, to the feed at FEED_PATH
+            and exit
+
+    fetch
+            Fetch URL and print the metadata found on the page. --timeout
+            overrides FEEDLYNX_FETCH_TIMEOUT and --max-redirects overrides
+            FEEDLYNX_MAX_REDIRECTS
 
 OPTIONS:
     -h, --help
@@ -59,6 +207,21 @@ OPTIONS:
     -V, --version
             Prints version information
 
+    -l, --listen ADDRESS
+            The address to serve on, optionally with a port, E.g.
+            `0.0.0.0:9000`. Overrides FEEDLYNX_ADDRESS.
+
+    -p, --port PORT
+            The port to serve on. Overrides FEEDLYNX_PORT and the port in
+            --listen.
+
+    -q, --quiet
+            Only log errors
+
+    -v, --verbose
+            Log debugging information, repeat (-vv) for more detail.
+            FEEDLYNX_LOG takes precedence when set.
+
 ENVIRONMENT:
 
     Required:
@@ -72,7 +235,8 @@ ENVIRONMENT:
     Optional:
 
         FEEDLYNX_ADDRESS
-            The address to serve on, default `{addr}`.
+            The address to serve on,
+            or a comma-separated list of addresses, default `{addr}`.
 
         FEEDLYNX_PORT
             The port to serve on, default `{port}`.
@@ -80,6 +244,132 @@ ENVIRONMENT:
         FEEDLYNX_LOG
             Controls the log level and filtering.
 
+        FEEDLYNX_LOG_FORMAT
+            `text` (default) for human readable logs or `json` to log each
+            record as a JSON object.
+
+        FEEDLYNX_FEED_ID
+            The id of the feed, used when the feed file is created. A random
+            id is generated when not set.
+
+        FEEDLYNX_PUBLIC_URL
+            The URL the server is publicly reachable at, used for URLs shown on
+            the index page. Derived from the request when not set.
+
+        FEEDLYNX_TRUST_PROXY
+            Set to `1` to use the X-Forwarded-Host and X-Forwarded-Proto
+            headers from a reverse proxy when deriving URLs from the request.
+
+        FEEDLYNX_TLS_CERT, FEEDLYNX_TLS_KEY
+            Paths to a PEM certificate chain and private key to serve HTTPS
+            with. Requires feedlynx to be built with the `tls` feature.
+
+        FEEDLYNX_CORS_ORIGIN
+            A comma-separated list of origins allowed to make cross-origin
+            requests. Any origin is allowed when not set.
+
+        FEEDLYNX_ALLOWED_DOMAINS
+            A comma-separated list of domains that links may be added from,
+            including their subdomains. Any domain is allowed when not set.
+
+        FEEDLYNX_BLOCKED_DOMAINS
+            A comma-separated list of domains, including their subdomains,
+            that links may not be added from, even if they're allowed.
+
+        FEEDLYNX_ALLOW_PRIVATE_HOSTS
+            Set to `1` to allow fetching links on private, loopback, and
+            link-local addresses.
+
+        FEEDLYNX_MAX_PAGE_SIZE
+            The maximum number of bytes of a page that are parsed for
+            metadata, default `{max_page_size}`.
+
+        FEEDLYNX_FETCH_TIMEOUT
+            Seconds allowed for each request when fetching pages, default
+            `{fetch_timeout}`.
+
+        FEEDLYNX_MIN_TITLE_LENGTH
+            Fetched titles shorter than this, after trimming whitespace, are
+            ignored in favour of the submitted title, default `{min_title}`.
+
+        FEEDLYNX_MAX_REDIRECTS
+            The maximum number of redirects followed when fetching pages,
+            default `{max_redirects}`.
+
+        FEEDLYNX_OEMBED_HOSTS
+            A comma-separated list of hosts whose pages are embedded in the
+            feed using their oEmbed endpoint, E.g. `vimeo.com`.
+
+        FEEDLYNX_MAX_SUMMARY
+            The maximum number of characters of a page description stored in
+            the feed, default `{max_summary}`. `0` means no limit.
+
+        FEEDLYNX_SUMMARY_FORMAT
+            The format of entry summaries, `html` (default) or `text`. Use
+            `text` for readers that render HTML summaries poorly.
+
+        FEEDLYNX_UNTITLED_TEXT
+            Title of entries for pages without one, default `Untitled`.
+
+        FEEDLYNX_ENTRY_ORDER
+            The order entries are listed in the served feed, `newest` first
+            (default) or `oldest` first.
+
+        FEEDLYNX_PRETTY_FEED
+            Set to `1` to indent the XML of the saved feed.
+
+        FEEDLYNX_KEEP_BACKUP
+            Set to `1` to keep the previous version of the feed in
+            FEED_PATH.bak when saving.
+
+        FEEDLYNX_REPAIR_FEED
+            Set to `1` to move a feed that can't be read to FEED_PATH.corrupt
+            and create a new one, instead of failing to start.
+
+        FEEDLYNX_FEEDS
+            Comma separated list of additional feeds to serve, in name=path
+            format. E.g. `watch=watch-later.xml`.
+
+        FEEDLYNX_ACCESS_LOG
+            Set to `1` to log each request at info level in Combined Log
+            Format.
+
+        FEEDLYNX_READ_TIMEOUT
+            Seconds allowed for receiving the body of a request, default
+            `{read_timeout}`.
+
+        FEEDLYNX_CACHE_MAX_AGE
+            Seconds feed responses may be cached for, sent in the
+            Cache-Control header, default `{cache_max_age}`. `0` disables the
+            header.
+
+        FEEDLYNX_ASYNC_FETCH
+            Set to `1` to respond to requests to add links before fetching
+            the page. The entry is updated once the page is fetched.
+
+        FEEDLYNX_BASE_PATH
+            Path prefix of all routes when hosted on a subpath behind a reverse
+            proxy. E.g. `/feedlynx`.
+
+        FEEDLYNX_AUTHOR_NAME, FEEDLYNX_AUTHOR_EMAIL, FEEDLYNX_AUTHOR_URI
+            The author of the feeds. Defaults to feedlynx and its homepage
+            when not set.
+
+        FEEDLYNX_FEED_RIGHTS
+            Copyright statement of the feeds, E.g. `© 2024 Jane Smith`.
+
+        FEEDLYNX_FEED_LOGO
+            URL of the logo image of the feeds.
+
+        FEEDLYNX_PUBLIC_METRICS
+            Set to `1` to serve /metrics without requiring the private token.
+
+        FEEDLYNX_SELFTEST
+            Set to `1` to fetch a page on startup to check that pages can be
+            fetched. FEEDLYNX_SELFTEST_URL sets the page, default the
+            feedlynx homepage. Set FEEDLYNX_SELFTEST_STRICT to `1` to exit
+            if it can't be fetched.
+
 AUTHOR
     {}
 
@@ -89,7 +379,14 @@ SEE ALSO
         env!("CARGO_PKG_AUTHORS"),
         bin = env!("CARGO_PKG_NAME"),
         addr = DEFAULT_ADDR,
-        port = DEFAULT_PORT
+        port = DEFAULT_PORT,
+        max_page_size = DEFAULT_MAX_PAGE_SIZE,
+        max_summary = DEFAULT_MAX_SUMMARY,
+        read_timeout = DEFAULT_READ_TIMEOUT.as_secs(),
+        cache_max_age = DEFAULT_CACHE_MAX_AGE.as_secs(),
+        fetch_timeout = DEFAULT_FETCH_TIMEOUT.as_secs(),
+        max_redirects = DEFAULT_MAX_REDIRECTS,
+        min_title = DEFAULT_MIN_TITLE_LENGTH
     );
     Ok(Command::Exit(ExitCode::SUCCESS))
 }
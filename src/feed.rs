@@ -1,31 +1,136 @@
-use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::{borrow::Cow, fs::File};
 use std::{fs, mem};
 
-use atom_syndication::{self as atom, Entry, Generator};
-use chrono::{DateTime, TimeDelta, Utc};
-use log::{info, trace};
-use uriparse::URI;
+use atom_syndication::{self as atom, Entry, Generator, WriteConfig};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use log::{debug, info, trace};
+use uriparse::{Scheme, URI};
 
-use crate::webpage::WebPage;
-use crate::{base62, Error};
+use crate::webpage::{self, Video, WebPage};
+use crate::{base62, gzip, Error};
 
 pub const MIN_ENTRIES: usize = 50;
 pub const TRIM_AGE: TimeDelta = TimeDelta::days(30);
 
+/// The scheme of the category used to record the Open Graph type of an entry
+const OG_TYPE_SCHEME: &str = "og:type";
+
+/// The term of the category marking an entry as read
+const READ_TERM: &str = "read";
+
+/// The scheme of the category marking an entry as read, so it's distinct from a `read` tag
+pub const READ_SCHEME: &str = "feedlynx:read";
+
+/// The scheme of the category recording where an entry was added from
+pub const SOURCE_SCHEME: &str = "feedlynx:source";
+
+/// The scheme of the category recording when an entry was added to the feed
+pub const ADDED_SCHEME: &str = "feedlynx:added";
+
 pub struct Feed {
     path: PathBuf,
     feed: atom_syndication::Feed,
+    entry_options: EntryOptions,
+}
+
+/// The number of spaces each level of XML is indented by when pretty printing
+const PRETTY_INDENT: usize = 2;
+
+/// Options that control how the feed is saved
+#[derive(Clone, Default)]
+pub struct SaveOptions {
+    /// Indent the XML so that it's easier to read and diff
+    pub pretty: bool,
+    /// Keep the previous version of the feed in a backup file, see [Feed::backup_path]
+    pub keep_backup: bool,
+}
+
+/// Options that control which entries are removed when the feed is trimmed
+#[derive(Clone, Copy)]
+pub struct TrimOptions {
+    /// The number of entries that are always kept
+    pub min_entries: usize,
+    /// Entries older than this are removed, unless they're needed to make up `min_entries`
+    pub max_age: TimeDelta,
+}
+
+impl Default for TrimOptions {
+    fn default() -> Self {
+        TrimOptions {
+            min_entries: MIN_ENTRIES,
+            max_age: TRIM_AGE,
+        }
+    }
+}
+
+/// The default title of entries for pages without one
+pub const DEFAULT_UNTITLED: &str = "Untitled";
+
+/// Options that control how new entries are built
+#[derive(Clone)]
+pub struct EntryOptions {
+    pub summary_format: SummaryFormat,
+    /// The title of entries for pages without one
+    pub untitled: String,
+}
+
+impl Default for EntryOptions {
+    fn default() -> Self {
+        EntryOptions {
+            summary_format: SummaryFormat::default(),
+            untitled: DEFAULT_UNTITLED.to_string(),
+        }
+    }
+}
+
+/// The format of the summaries of new entries
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SummaryFormat {
+    /// HTML when it adds something, like an embedded YouTube player or a link to the page
+    #[default]
+    Html,
+    /// Always plain text, for readers that render HTML summaries poorly
+    Text,
+}
+
+/// The order entries are listed in when the feed is served
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// The most recently added entry first, which is what most readers expect
+    #[default]
+    Newest,
+    /// The order entries are stored in, the oldest first
+    Oldest,
+}
+
+/// The outcome of [Feed::add_url_if_new]
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddResult {
+    /// The link was added to the feed in a new entry with this id
+    Added(String),
+    /// The feed already had an entry for the link, the feed was not modified
+    Duplicate,
 }
 
 impl Feed {
+    /// Read the feed at `path`, which is gzip compressed if it ends in `.gz`.
     pub fn read<P: Into<PathBuf>>(path: P) -> Result<Feed, Error> {
         let path = path.into();
-        let file = File::open(&path)?;
-        let feed = atom::Feed::read_from(BufReader::new(file))?;
+        let data = fs::read(&path)?;
+        let feed = if gzip::is_gzip_path(&path) {
+            atom::Feed::read_from(gzip::decompress(&data)?.as_slice())?
+        } else {
+            atom::Feed::read_from(data.as_slice())?
+        };
 
-        Ok(Feed { feed, path })
+        Ok(Feed {
+            feed,
+            path,
+            entry_options: EntryOptions::default(),
+        })
     }
 
     /// Construct a new, empty feed
@@ -40,6 +145,7 @@ impl Feed {
         let mut feed = Feed {
             feed,
             path: path.into(),
+            entry_options: EntryOptions::default(),
         };
         feed.set_feed_id();
         feed.set_feed_author();
@@ -47,60 +153,286 @@ impl Feed {
         feed
     }
 
-    pub fn add_url(&mut self, url: &URI, page: WebPage) {
+    /// Set the options used to build entries added or updated from now on.
+    pub fn set_entry_options(&mut self, options: EntryOptions) {
+        self.entry_options = options;
+    }
+
+    /// Add an entry for `url` to the feed.
+    ///
+    /// `tags` are added to the entry as categories. The time the entry was added is recorded as
+    /// a category with the [ADDED_SCHEME] scheme, see [added_at]. Returns the id of the new entry.
+    pub fn add_url(&mut self, url: &URI, page: WebPage, tags: &[String]) -> String {
         info!("Add {}", url);
         let now: DateTime<Utc> = Utc::now();
 
         // Add the new item
-        let link = atom::Link {
-            href: url.to_string(),
-            rel: "alternate".to_string(),
-            ..Default::default()
-        };
-        let authors = page
-            .author
-            .map(|author| {
-                vec![atom::Person {
-                    name: author,
-                    ..Default::default()
-                }]
-            })
-            .unwrap_or_default();
-        let entry = atom::Entry {
-            title: page.title.unwrap_or_else(|| "Untitled".to_string()).into(),
-            id: unique_tag_id(),
-            updated: now.into(),
-            summary: Some(summary_for_url(url, page.description)),
-            links: vec![link],
-            authors,
+        let id = self.new_entry_id(unique_tag_id);
+        let mut entry = new_entry(id.clone(), url, page, tags, now, &self.entry_options);
+        // published may come from the page, so keep the time it was added separately
+        entry.categories.push(atom::Category {
+            term: now.to_rfc3339(),
+            scheme: Some(ADDED_SCHEME.to_string()),
             ..Default::default()
-        };
+        });
         self.feed.entries.push(entry);
         self.set_generator();
         self.feed.set_updated(now);
+        id
+    }
+
+    /// Generate an id for a new entry with `generate`, which is not used by any existing entry.
+    fn new_entry_id(&self, mut generate: impl FnMut() -> String) -> String {
+        loop {
+            let id = generate();
+            if !self.feed.entries.iter().any(|entry| entry.id() == id) {
+                return id;
+            }
+            debug!("Generated duplicate entry id {id}");
+        }
+    }
+
+    /// Replace the metadata of the entry with `id` with that of `page`, fetched from `url`.
+    ///
+    /// Tags and other categories added to the entry are kept. Returns `false` if there is no
+    /// entry with `id`.
+    pub fn update_url(&mut self, id: &str, url: &URI, page: WebPage) -> bool {
+        let now: DateTime<Utc> = Utc::now();
+        let Some(entry) = self.feed.entries.iter_mut().find(|entry| entry.id() == id) else {
+            return false;
+        };
+
+        info!("Update {}", url);
+        let page_published = page.published.is_some();
+        let mut updated = new_entry(id.to_string(), url, page, &[], now, &self.entry_options);
+        // Keep the categories that didn't come from the page
+        updated.categories.extend(
+            entry
+                .categories
+                .drain(..)
+                .filter(|category| category.scheme() != Some(OG_TYPE_SCHEME)),
+        );
+        // Keep the time the entry was added if the page doesn't say when it was published
+        if !page_published {
+            updated.published = entry.published;
+        }
+        *entry = updated;
+        self.feed.set_updated(now);
+        true
+    }
+
+    /// Add an entry for `url` to the feed, unless there is already an entry for it.
+    pub fn add_url_if_new(&mut self, url: &URI, page: WebPage, tags: &[String]) -> AddResult {
+        if self.contains_url(url) {
+            return AddResult::Duplicate;
+        }
+        AddResult::Added(self.add_url(url, page, tags))
+    }
+
+    /// Determine if the feed has an entry linking to `url`.
+    ///
+    /// URLs are compared after normalisation with [normalize_url]. The short URL of a page, from
+    /// `<link rel="shortlink">`, is also considered a link to it.
+    pub fn contains_url(&self, url: &URI) -> bool {
+        let href = normalize_url(url);
+        self.feed.entries.iter().any(|entry| {
+            entry.links().iter().any(|link| {
+                // Entries added before normalisation was introduced may not be normalised
+                matches!(link.rel(), "alternate" | "related")
+                    && URI::try_from(link.href())
+                        .map(|link_url| normalize_url(&link_url) == href)
+                        .unwrap_or_else(|_| link.href() == href)
+            })
+        })
+    }
+
+    /// Mark the entry with `id` as read or unread.
+    ///
+    /// Read entries have a category with the term `read` and the [READ_SCHEME] scheme. Returns
+    /// `false` if there is no entry with `id`.
+    pub fn set_read(&mut self, id: &str, read: bool) -> bool {
+        let Some(entry) = self.feed.entries.iter_mut().find(|entry| entry.id() == id) else {
+            return false;
+        };
+        entry.categories.retain(|category| {
+            category.scheme() != Some(READ_SCHEME) || category.term() != READ_TERM
+        });
+        if read {
+            entry.categories.push(atom::Category {
+                term: READ_TERM.to_string(),
+                scheme: Some(READ_SCHEME.to_string()),
+                ..Default::default()
+            });
+        }
+        true
+    }
+
+    /// Record where the entry with `id` was added from, E.g. the app it was shared from.
+    ///
+    /// The source is stored as a category with the [SOURCE_SCHEME] scheme. Returns `false` if
+    /// there is no entry with `id`.
+    pub fn set_source(&mut self, id: &str, source: &str) -> bool {
+        let Some(entry) = self.feed.entries.iter_mut().find(|entry| entry.id() == id) else {
+            return false;
+        };
+        entry
+            .categories
+            .retain(|category| category.scheme() != Some(SOURCE_SCHEME));
+        entry.categories.push(atom::Category {
+            term: source.to_string(),
+            scheme: Some(SOURCE_SCHEME.to_string()),
+            ..Default::default()
+        });
+        true
+    }
+
+    /// The number of entries added from each source, see [Feed::set_source].
+    pub fn source_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        self.feed
+            .entries
+            .iter()
+            .flat_map(|entry| entry.categories())
+            .filter(|category| category.scheme() == Some(SOURCE_SCHEME))
+            .for_each(|category| *counts.entry(category.term()).or_insert(0) += 1);
+        counts
+    }
+
+    /// Set the `self` link of the feed, which is the URL the feed is served from.
+    pub fn set_self_link(&mut self, href: String) {
+        match self.feed.links.iter_mut().find(|link| link.rel() == "self") {
+            Some(link) => link.href = href,
+            None => self.feed.links.push(atom::Link {
+                href,
+                rel: "self".to_string(),
+                mime_type: Some("application/atom+xml".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The id of the feed.
+    pub fn id(&self) -> &str {
+        self.feed.id()
+    }
+
+    /// The time the feed was last updated, when an entry was last added or updated.
+    pub fn updated(&self) -> DateTime<FixedOffset> {
+        *self.feed.updated()
+    }
+
+    /// Set the id of the feed, I.e. atom:id.
+    ///
+    /// This should be a permanent, universally unique IRI.
+    pub fn set_id(&mut self, id: &str) {
+        self.feed.set_id(id);
+    }
+
+    /// The entries in the feed, oldest first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.feed.entries
+    }
+
+    /// Trim entries older than the `max_age` of `options`, but keep `min_entries`.
+    ///
+    /// Returns the number of entries left in the feed.
+    pub fn trim_entries(&mut self, options: &TrimOptions) -> usize {
+        trim_entries(&mut self.feed.entries, options.min_entries, options.max_age);
+        self.feed.entries.len()
+    }
+
+    /// Remove all the entries from the feed, keeping its id, title, and author.
+    ///
+    /// Returns the number of entries removed.
+    pub fn clear_entries(&mut self) -> usize {
+        let removed = self.feed.entries.len();
+        self.feed.entries.clear();
+        self.feed.set_updated(Utc::now());
+        removed
     }
 
-    /// Trim entries older than `trim_age`, but keep `min_entries`.
-    pub fn trim_entries(&mut self) {
-        trim_entries(&mut self.feed.entries, MIN_ENTRIES, TRIM_AGE);
+    /// Keep only the entries tagged with `tag`.
+    ///
+    /// Tags are the categories without a scheme, so other categories like the Open Graph type
+    /// are not matched.
+    pub fn retain_tag(&mut self, tag: &str) {
+        self.feed.entries.retain(|entry| {
+            entry
+                .categories()
+                .iter()
+                .any(|category| category.scheme().is_none() && category.term() == tag)
+        });
+    }
+
+    /// Keep only the entries updated after `since`.
+    pub fn retain_since(&mut self, since: DateTime<FixedOffset>) {
+        self.feed.entries.retain(|entry| *entry.updated() > since);
+    }
+
+    /// List the entries in `order`.
+    ///
+    /// Entries are stored in the order they were added so this is for serving the feed, saving
+    /// it afterwards would change the stored order.
+    pub fn order_entries(&mut self, order: EntryOrder) {
+        if order == EntryOrder::Newest {
+            self.feed.entries.reverse();
+        }
+    }
+
+    /// Serialise the feed to XML, uncompressed.
+    pub fn to_xml(&self, options: &SaveOptions) -> Result<Vec<u8>, Error> {
+        let config = WriteConfig {
+            indent_size: options.pretty.then_some(PRETTY_INDENT),
+            ..Default::default()
+        };
+        Ok(self.feed.write_with_config(Vec::new(), config)?)
     }
 
-    pub fn save(&self) -> Result<(), Error> {
+    pub fn save(&self, options: &SaveOptions) -> Result<(), Error> {
         let tmp_path = self.path.with_extension("tmp");
 
         // Wrap in block so that tmp_file is dropped before calling rename
         {
+            let mut xml = self.to_xml(options)?;
+            if gzip::is_gzip_path(&self.path) {
+                xml = gzip::compress(&xml);
+            }
+
             // Write out the file entirely
-            let tmp_file = File::create(&tmp_path)?;
-            let writer = BufWriter::new(tmp_file);
-            let mut writer = self.feed.write_to(writer)?;
-            writer.flush()?;
+            let mut tmp_file = File::create(&tmp_path)?;
+            tmp_file.write_all(&xml)?;
+            // Ensure the data is on disk before it replaces the existing feed
+            tmp_file.sync_all()?;
             trace!("Wrote {}", tmp_path.display())
         }
 
+        // The backup is a link to, or copy of, the current feed so that the feed itself is only
+        // ever replaced by the rename below and never missing
+        if options.keep_backup && self.path.exists() {
+            let backup_path = Feed::backup_path(&self.path);
+            trace!("Link {} -> {}", self.path.display(), backup_path.display());
+            match fs::remove_file(&backup_path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            if let Err(err) = fs::hard_link(&self.path, &backup_path) {
+                debug!("Unable to link backup, copying instead: {}", err);
+                fs::copy(&self.path, &backup_path)?;
+            }
+        }
+
         // Move into place atomically
         trace!("Move {} -> {}", tmp_path.display(), self.path.display());
-        fs::rename(tmp_path, &self.path).map_err(Error::from)
+        fs::rename(tmp_path, &self.path)?;
+        sync_parent_dir(&self.path);
+        Ok(())
+    }
+
+    /// The path of the backup kept when saving the feed at `path`, E.g. `feed.xml.bak`.
+    pub fn backup_path(path: &Path) -> PathBuf {
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push(".bak");
+        PathBuf::from(backup_path)
     }
 
     /// Generate a new, unique id for this feed according to the [tag]
@@ -116,12 +448,50 @@ impl Feed {
     /// Atom requires that the feed has an author or every entry does. Since we
     /// start off with an empty feed a default author is populated.
     fn set_feed_author(&mut self) {
-        let author = atom::Person {
+        self.set_author(Self::default_author());
+    }
+
+    /// The default author of the feed: the package name and homepage.
+    pub fn default_author() -> atom::Person {
+        atom::Person {
             name: env!("CARGO_PKG_NAME").to_string(),
             uri: Some(env!("CARGO_PKG_HOMEPAGE").to_string()),
             ..Default::default()
-        };
+        }
+    }
+
+    /// Set the author of the feed, replacing any existing authors.
+    ///
+    /// Returns `false` if the feed already had just this author.
+    pub fn set_author(&mut self, author: atom::Person) -> bool {
+        if self.feed.authors() == std::slice::from_ref(&author) {
+            return false;
+        }
         self.feed.set_authors(vec![author]);
+        true
+    }
+
+    /// Set the copyright or other rights statement of the feed.
+    ///
+    /// Returns `false` if the feed already had these rights.
+    pub fn set_rights(&mut self, rights: &str) -> bool {
+        let rights = atom::Text::plain(rights);
+        if self.feed.rights() == Some(&rights) {
+            return false;
+        }
+        self.feed.set_rights(rights);
+        true
+    }
+
+    /// Set the URL of the logo image of the feed.
+    ///
+    /// Returns `false` if the feed already had this logo.
+    pub fn set_logo(&mut self, logo: &str) -> bool {
+        if self.feed.logo() == Some(logo) {
+            return false;
+        }
+        self.feed.set_logo(logo.to_string());
+        true
     }
 
     /// Set the generator of the feed
@@ -137,12 +507,101 @@ impl Feed {
     }
 }
 
+/// The time `entry` was added to the feed, as recorded by [Feed::add_url].
+///
+/// Returns `None` for entries added before this was recorded.
+pub fn added_at(entry: &Entry) -> Option<DateTime<FixedOffset>> {
+    entry
+        .categories()
+        .iter()
+        .find(|category| category.scheme() == Some(ADDED_SCHEME))
+        .and_then(|category| DateTime::parse_from_rfc3339(category.term()).ok())
+}
+
+/// Build the entry for `url` from the metadata in `page`
+fn new_entry(
+    id: String,
+    url: &URI,
+    page: WebPage,
+    tags: &[String],
+    now: DateTime<Utc>,
+    options: &EntryOptions,
+) -> Entry {
+    let link = atom::Link {
+        href: normalize_url(url),
+        rel: "alternate".to_string(),
+        ..Default::default()
+    };
+    let mut links = vec![link];
+    if let Some(image) = page.image.as_deref().and_then(image_enclosure) {
+        links.push(image);
+    }
+    // The short URL identifies the same page, see [Feed::contains_url]
+    if let Some(shortlink) = page
+        .shortlink
+        .as_deref()
+        .and_then(|shortlink| URI::try_from(shortlink).ok())
+    {
+        links.push(atom::Link {
+            href: normalize_url(&shortlink),
+            rel: "related".to_string(),
+            ..Default::default()
+        });
+    }
+    let authors = page
+        .author
+        .map(|author| {
+            vec![atom::Person {
+                name: author,
+                ..Default::default()
+            }]
+        })
+        .unwrap_or_default();
+    let categories = page
+        .og_type
+        .map(|og_type| atom::Category {
+            term: og_type,
+            scheme: Some(OG_TYPE_SCHEME.to_string()),
+            ..Default::default()
+        })
+        .into_iter()
+        .chain(tags.iter().map(|tag| atom::Category {
+            term: tag.clone(),
+            ..Default::default()
+        }))
+        .collect();
+    atom::Entry {
+        title: page
+            .title
+            .filter(|title| !title.trim().is_empty())
+            .unwrap_or_else(|| options.untitled.clone())
+            .into(),
+        id,
+        updated: now.into(),
+        // The time the page was published if known, otherwise when it was added. updated is
+        // left for modifications to the entry.
+        published: Some(page.published.unwrap_or_else(|| now.into())),
+        summary: Some(summary_for_url(
+            url,
+            page.description,
+            page.embed_html,
+            page.video.as_ref(),
+            options.summary_format,
+        )),
+        links,
+        authors,
+        categories,
+        ..Default::default()
+    }
+}
+
 fn trim_entries(entries: &mut Vec<Entry>, min_entries: usize, trim_age: TimeDelta) {
     if entries.len() <= min_entries {
         return;
     }
 
-    // Sort by age (oldest first) so that old items are dropped first.
+    // Sort by age (oldest first) so that old items are dropped first. This uses updated rather
+    // than published since published may be the date of the page, not when it was added.
     // This is not really necessary since the entries should be in this order already,
     // but we'll be sure.
     entries.sort_by(|a, b| a.updated().cmp(b.updated()));
@@ -169,26 +628,179 @@ fn trim_entries(entries: &mut Vec<Entry>, min_entries: usize, trim_age: TimeDelt
         .collect();
 }
 
-fn summary_for_url(url: &URI, description: Option<String>) -> atom::Text {
-    let video_id = is_youtube(url).then(|| youtube_video_id(url)).flatten();
-    if let Some(video_id) = video_id {
-        let mut summary = format!(
+/// Build the summary of an entry for `url`.
+///
+/// HTML summaries start with `embed_html`, from the page's oEmbed endpoint, an embedded
+/// player for YouTube videos, or the `og:video` of the page.
+fn summary_for_url(
+    url: &URI,
+    description: Option<String>,
+    embed_html: Option<String>,
+    video: Option<&Video>,
+    format: SummaryFormat,
+) -> atom::Text {
+    if format == SummaryFormat::Text {
+        return match description {
+            Some(desc) => atom::Text::plain(webpage::strip_tags(&desc)),
+            None => atom::Text::plain(url.to_string()),
+        };
+    }
+
+    let embed = embed_html.or_else(|| {
+        let video_id = is_youtube(url).then(|| youtube_video_id(url)).flatten()?;
+        Some(format!(
             r#"<iframe width="560" height="315" src="https://www.youtube.com/embed/{video_id}" title="YouTube video player" frameborder="0" allow="accelerometer; autoplay; clipboard-write; encrypted-media; gyroscope; picture-in-picture; web-share" referrerpolicy="strict-origin-when-cross-origin" allowfullscreen></iframe>"#,
-        );
+        ))
+    });
+    let embed = embed.or_else(|| video.and_then(video_embed));
+    if let Some(mut summary) = embed {
         if let Some(desc) = description.as_deref() {
             summary.push_str("<div>");
-            summary.push_str(desc); // description is expected to be plain text
+            summary.push_str(&webpage::escape_html(&webpage::strip_tags(desc)));
             summary.push_str("</div>");
         }
         atom::Text::html(summary)
     } else {
         match description {
-            Some(desc) => atom::Text::plain(desc),
+            Some(desc) => atom::Text::plain(webpage::strip_tags(&desc)),
             None => atom::Text::html(format!(r#"<a href="{url}">{url}</a>"#)),
         }
     }
 }
 
+/// Sync the directory containing `path` so that a rename into it is durable.
+///
+/// This is best-effort: directories can't be opened or synced on all platforms, so failure is
+/// only logged.
+fn sync_parent_dir(path: &Path) {
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    // An empty parent means the path is relative to the current directory
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if let Err(err) = File::open(dir).and_then(|dir| dir.sync_all()) {
+        debug!("Unable to sync directory {}: {err}", dir.display());
+    }
+}
+
+/// Normalise a URL so that equivalent URLs compare equal.
+///
+/// In addition to the normalisation performed by [URI::normalize] (case of scheme and host,
+/// percent-encoding, and dot segments) the default port and fragment are removed, an empty path
+/// becomes `/`, and query parameters are sorted.
+pub(crate) fn normalize_url(url: &URI) -> String {
+    let mut url = url.clone();
+    url.normalize();
+
+    let default_port = match url.scheme() {
+        Scheme::HTTP => Some(80),
+        Scheme::HTTPS => Some(443),
+        _ => None,
+    };
+    if url.port().is_some() && url.port() == default_port {
+        url.map_authority(|authority| {
+            authority.map(|mut authority| {
+                authority.set_port(None);
+                authority
+            })
+        });
+    }
+    if url.path().to_string().is_empty() {
+        // NOTE(unwrap): "/" is a valid path
+        url.set_path("/").unwrap();
+    }
+
+    let mut params = url
+        .query()
+        .map(|query| {
+            query
+                .as_str()
+                .split('&')
+                .filter(|param| !param.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    params.sort_unstable();
+    let query = params.join("&");
+
+    // NOTE(unwrap): None is always valid for these
+    url.set_query(None::<&str>).unwrap();
+    url.set_fragment(None::<&str>).unwrap();
+
+    let mut normalized = url.to_string();
+    if !query.is_empty() {
+        normalized.push('?');
+        normalized.push_str(&query);
+    }
+    normalized
+}
+
+/// Build an enclosure link for an image, if the image URL is valid and HTTP(S)
+fn image_enclosure(image: &str) -> Option<atom::Link> {
+    let uri = URI::try_from(image).ok()?;
+    if !matches!(uri.scheme(), Scheme::HTTP | Scheme::HTTPS) {
+        return None;
+    }
+    let extension = uri
+        .path()
+        .segments()
+        .last()
+        .and_then(|segment| segment.as_str().rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    let mime_type = match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("avif") => "image/avif",
+        _ => "image/jpeg",
+    };
+    Some(atom::Link {
+        href: uri.to_string(),
+        rel: "enclosure".to_string(),
+        mime_type: Some(mime_type.to_string()),
+        ..Default::default()
+    })
+}
+
+/// Build the HTML to embed an `og:video`.
+///
+/// Video files are played with `<video>`, anything else is assumed to be a player page and
+/// shown in an `<iframe>`. Returns `None` if the URL isn't HTTP(S).
+fn video_embed(video: &Video) -> Option<String> {
+    let uri = URI::try_from(video.url.as_str()).ok()?;
+    if !matches!(uri.scheme(), Scheme::HTTP | Scheme::HTTPS) {
+        return None;
+    }
+    let is_file = match video.mime_type.as_deref() {
+        Some(mime_type) => mime_type.starts_with("video/"),
+        None => uri
+            .path()
+            .segments()
+            .last()
+            .and_then(|segment| segment.as_str().rsplit_once('.'))
+            .is_some_and(|(_, extension)| {
+                matches!(
+                    extension.to_ascii_lowercase().as_str(),
+                    "mp4" | "webm" | "ogv" | "mov" | "m4v"
+                )
+            }),
+    };
+    let src = uri.to_string();
+    let src = webpage::escape_html(&src);
+    if is_file {
+        Some(format!(r#"<video src="{src}" controls></video>"#))
+    } else {
+        Some(format!(
+            r#"<iframe width="560" height="315" src="{src}" frameborder="0" allowfullscreen></iframe>"#
+        ))
+    }
+}
+
 fn is_youtube(url: &URI) -> bool {
     let Some(host) = url.host() else {
         return false;
@@ -262,6 +874,664 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_retain_tag() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/rust").unwrap();
+        feed.add_url(&url, WebPage::default(), &["rust".to_string()]);
+        let url = URI::try_from("https://example.com/video").unwrap();
+        let page = WebPage {
+            og_type: Some("rust".to_string()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &["video".to_string()]);
+
+        feed.retain_tag("rust");
+        assert_eq!(feed.entries().len(), 1);
+        assert_eq!(
+            feed.entries()[0].links()[0].href(),
+            "https://example.com/rust"
+        );
+    }
+
+    #[test]
+    fn test_retain_since() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let since = DateTime::parse_from_rfc3339("2024-06-01T00:00:00Z").unwrap();
+        feed.feed.entries = vec![
+            test_entry("Old".into(), since - TimeDelta::days(1)),
+            test_entry("Same".into(), since),
+            test_entry("New".into(), since + TimeDelta::seconds(1)),
+        ];
+
+        feed.retain_since(since);
+        let titles = feed
+            .entries()
+            .iter()
+            .map(|entry| entry.title().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["New"]);
+    }
+
+    #[test]
+    fn test_order_entries() {
+        let mut feed = Feed::generate_new("unused.xml");
+        for path in ["one", "two", "three"] {
+            let url = format!("https://example.com/{path}");
+            let url = URI::try_from(url.as_str()).unwrap();
+            feed.add_url(&url, WebPage::default(), &[]);
+        }
+        let urls = |feed: &Feed| {
+            feed.entries()
+                .iter()
+                .map(|entry| entry.links()[0].href().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        feed.order_entries(EntryOrder::Oldest);
+        assert_eq!(
+            urls(&feed),
+            [
+                "https://example.com/one",
+                "https://example.com/two",
+                "https://example.com/three"
+            ]
+        );
+        feed.order_entries(EntryOrder::Newest);
+        assert_eq!(
+            urls(&feed),
+            [
+                "https://example.com/three",
+                "https://example.com/two",
+                "https://example.com/one"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_author() {
+        let mut feed = Feed::generate_new("unused.xml");
+        assert_eq!(feed.feed.authors(), [Feed::default_author()]);
+        assert!(!feed.set_author(Feed::default_author()));
+
+        let author = atom::Person {
+            name: "Jane Smith".to_string(),
+            email: Some("jane@example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(feed.set_author(author.clone()));
+        assert_eq!(feed.feed.authors(), std::slice::from_ref(&author));
+        assert!(!feed.set_author(author));
+    }
+
+    #[test]
+    fn test_untitled() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        assert_eq!(feed.entries().last().unwrap().title().as_str(), "Untitled");
+
+        feed.set_entry_options(EntryOptions {
+            untitled: "Sans titre".to_string(),
+            ..Default::default()
+        });
+        let url = URI::try_from("https://example.com/other").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        assert_eq!(
+            feed.entries().last().unwrap().title().as_str(),
+            "Sans titre"
+        );
+    }
+
+    #[test]
+    fn test_set_rights_and_logo() {
+        let mut feed = Feed::generate_new("unused.xml");
+        assert_eq!(feed.feed.rights(), None);
+        assert_eq!(feed.feed.logo(), None);
+
+        assert!(feed.set_rights("© 2024 Jane Smith"));
+        assert!(!feed.set_rights("© 2024 Jane Smith"));
+        assert!(feed.set_logo("https://example.com/logo.png"));
+        assert!(!feed.set_logo("https://example.com/logo.png"));
+
+        // Unchanged after saving and reading back
+        let xml = feed.to_xml(&SaveOptions::default()).unwrap();
+        let mut feed = Feed {
+            feed: atom::Feed::read_from(xml.as_slice()).unwrap(),
+            path: PathBuf::from("unused.xml"),
+            entry_options: EntryOptions::default(),
+        };
+        assert_eq!(feed.feed.rights().unwrap().as_str(), "© 2024 Jane Smith");
+        assert!(!feed.set_rights("© 2024 Jane Smith"));
+        assert!(!feed.set_logo("https://example.com/logo.png"));
+    }
+
+    #[test]
+    fn test_clear_entries() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let id = feed.feed.id().to_string();
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        feed.add_url(&url, WebPage::default(), &[]);
+
+        assert_eq!(feed.clear_entries(), 2);
+        assert!(feed.entries().is_empty());
+        assert_eq!(feed.feed.id(), id);
+        assert_eq!(feed.clear_entries(), 0);
+    }
+
+    #[test]
+    fn test_add_url_author() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let page = WebPage {
+            title: Some("Post".to_string()),
+            author: Some("Jane Smith".to_string()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let entry = feed.feed.entries().last().unwrap();
+        assert_eq!(entry.authors()[0].name(), "Jane Smith");
+    }
+
+    #[test]
+    fn test_updated() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.feed.set_updated(DateTime::UNIX_EPOCH);
+        let AddResult::Added(id) = feed.add_url_if_new(&url, WebPage::default(), &[]) else {
+            panic!("link was not added")
+        };
+        let added = feed.updated();
+        assert!(added > DateTime::UNIX_EPOCH);
+
+        // Duplicates, and metadata that isn't about the entries, don't change it
+        feed.feed.set_updated(DateTime::UNIX_EPOCH);
+        assert_eq!(
+            feed.add_url_if_new(&url, WebPage::default(), &[]),
+            AddResult::Duplicate
+        );
+        feed.set_source(&id, "test");
+        feed.set_self_link("https://example.com/feed".to_string());
+        assert_eq!(feed.updated(), DateTime::UNIX_EPOCH);
+
+        assert!(feed.update_url(&id, &url, WebPage::default()));
+        assert!(feed.updated() > DateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_add_url_published() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        let entry = feed.feed.entries().last().unwrap();
+        assert_eq!(entry.published(), Some(entry.updated()));
+
+        let published = DateTime::parse_from_rfc3339("2024-06-24T09:30:00+10:00").unwrap();
+        let page = WebPage {
+            published: Some(published),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let entry = feed.feed.entries().last().unwrap();
+        assert_eq!(entry.published(), Some(&published));
+        assert!(entry.updated() > &published);
+    }
+
+    #[test]
+    fn test_update_url() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let page = WebPage {
+            title: Some("Provisional".to_string()),
+            ..Default::default()
+        };
+        let id = feed.add_url(&url, page, &["rust".to_string()]);
+        let published = *feed.entries()[0].published().unwrap();
+        let added = added_at(&feed.entries()[0]).unwrap();
+
+        let page = WebPage {
+            title: Some("Fetched".to_string()),
+            og_type: Some("article".to_string()),
+            ..Default::default()
+        };
+        assert!(feed.update_url(&id, &url, page));
+        let entry = &feed.entries()[0];
+        assert_eq!(entry.id(), id);
+        assert_eq!(entry.title().as_str(), "Fetched");
+        assert_eq!(entry.published(), Some(&published));
+        let terms = entry
+            .categories()
+            .iter()
+            .filter(|category| category.scheme() != Some(ADDED_SCHEME))
+            .map(|category| category.term())
+            .collect::<Vec<_>>();
+        assert_eq!(terms, ["article", "rust"]);
+        assert_eq!(added_at(entry), Some(added));
+
+        assert!(!feed.update_url("unknown", &url, WebPage::default()));
+    }
+
+    #[test]
+    fn test_set_self_link() {
+        let mut feed = Feed::generate_new("unused.xml");
+        feed.set_self_link("http://localhost/feed/token".to_string());
+        feed.set_self_link("https://example.com/feed/token".to_string());
+        let links = feed.feed.links();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].rel(), "self");
+        assert_eq!(links[0].href(), "https://example.com/feed/token");
+        assert_eq!(links[0].mime_type(), Some("application/atom+xml"));
+    }
+
+    #[test]
+    fn test_new_entry_id() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let existing = feed.add_url(&url, WebPage::default(), &[]);
+
+        let mut ids = vec!["tag:fresh".to_string(), existing.clone(), existing];
+        let id = feed.new_entry_id(|| ids.pop().unwrap());
+        assert_eq!(id, "tag:fresh");
+    }
+
+    #[test]
+    fn test_set_source() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let id = feed.add_url(&url, WebPage::default(), &[]);
+        assert!(feed.set_source(&id, "bookmarklet"));
+        assert!(feed.set_source(&id, "share-sheet"));
+        let sources = feed.entries()[0]
+            .categories()
+            .iter()
+            .filter(|category| category.scheme() == Some(SOURCE_SCHEME))
+            .map(|category| category.term())
+            .collect::<Vec<_>>();
+        assert_eq!(sources, ["share-sheet"]);
+        assert!(!feed.set_source("unknown", "bookmarklet"));
+
+        let id = feed.add_url(&url, WebPage::default(), &[]);
+        feed.set_source(&id, "share-sheet");
+        feed.add_url(&url, WebPage::default(), &[]);
+        assert_eq!(
+            feed.source_counts().into_iter().collect::<Vec<_>>(),
+            [("share-sheet", 2)]
+        );
+    }
+
+    #[test]
+    fn test_set_read() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let tags = ["rust".to_string(), "read".to_string()];
+        let id = feed.add_url(&url, WebPage::default(), &tags);
+        let categories = |feed: &Feed| {
+            feed.entries()[0]
+                .categories()
+                .iter()
+                .filter(|category| category.scheme() != Some(ADDED_SCHEME))
+                .map(|category| (category.term().to_string(), category.scheme().is_some()))
+                .collect::<Vec<_>>()
+        };
+        let tagged = [("rust".to_string(), false), ("read".to_string(), false)];
+
+        assert!(feed.set_read(&id, true));
+        assert!(feed.set_read(&id, true));
+        let mut read = tagged.to_vec();
+        read.push(("read".to_string(), true));
+        assert_eq!(categories(&feed), read);
+        // The read tag is kept when the entry is marked unread
+        assert!(feed.set_read(&id, false));
+        assert_eq!(categories(&feed), tagged);
+        assert!(!feed.set_read("unknown", true));
+    }
+
+    #[test]
+    fn test_add_url_tags() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let page = WebPage {
+            og_type: Some("article".to_string()),
+            ..Default::default()
+        };
+        let id = feed.add_url(&url, page, &["rust".to_string(), "later".to_string()]);
+        let entry = feed.feed.entries().last().unwrap();
+        assert_eq!(entry.id(), id);
+        let terms = entry
+            .categories()
+            .iter()
+            .filter(|category| category.scheme() != Some(ADDED_SCHEME))
+            .map(|category| (category.term(), category.scheme()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            terms,
+            [
+                ("article", Some(OG_TYPE_SCHEME)),
+                ("rust", None),
+                ("later", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_url_if_new() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let AddResult::Added(id) = feed.add_url_if_new(&url, WebPage::default(), &[]) else {
+            panic!("expected link to be added");
+        };
+        assert_eq!(feed.entries().last().unwrap().id(), id);
+        assert_eq!(
+            feed.add_url_if_new(&url, WebPage::default(), &[]),
+            AddResult::Duplicate
+        );
+        assert_eq!(feed.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_add_url_shortlink() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/2024/01/a-long-post-title").unwrap();
+        let page = WebPage {
+            shortlink: Some("https://example.com/?p=123".to_string()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let links = feed.entries()[0].links();
+        assert_eq!(links[1].rel(), "related");
+        assert_eq!(links[1].href(), "https://example.com/?p=123");
+
+        // The short URL is a duplicate of the page
+        let short = URI::try_from("https://example.com/?p=123").unwrap();
+        assert!(feed.contains_url(&short));
+    }
+
+    #[test]
+    fn test_save_pretty() {
+        let path = std::env::temp_dir().join(format!("feed.{}.xml", base62::base62::<8>()));
+        let mut feed = Feed::generate_new(&path);
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        feed.save(&SaveOptions {
+            pretty: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let xml = fs::read_to_string(&path).unwrap();
+        let read = Feed::read(&path);
+        let _ = fs::remove_file(&path);
+        assert!(xml.contains("\n  <entry>"), "{xml}");
+        assert_eq!(read.unwrap().entries().len(), 1);
+    }
+
+    #[test]
+    fn test_added_at() {
+        let path = std::env::temp_dir().join(format!("feed.{}.xml", base62::base62::<8>()));
+        let mut feed = Feed::generate_new(&path);
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let page = WebPage {
+            published: Some(DateTime::parse_from_rfc3339("2020-01-02T03:04:05Z").unwrap()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let added = added_at(&feed.entries()[0]).unwrap();
+        assert_ne!(feed.entries()[0].published(), Some(&added));
+        feed.save(&SaveOptions::default()).unwrap();
+
+        let read = Feed::read(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(added_at(&read.unwrap().entries()[0]), Some(added));
+        assert_eq!(
+            added_at(&test_entry("Untracked".into(), Utc::now().into())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_save_keep_backup() {
+        let path = std::env::temp_dir().join(format!("feed.{}.xml", base62::base62::<8>()));
+        let backup_path = Feed::backup_path(&path);
+        let options = SaveOptions {
+            keep_backup: true,
+            ..Default::default()
+        };
+        let mut feed = Feed::generate_new(&path);
+        feed.save(&options).unwrap();
+        let no_backup = !backup_path.exists();
+        let url = URI::try_from("https://example.com/post").unwrap();
+        feed.add_url(&url, WebPage::default(), &[]);
+        feed.save(&options).unwrap();
+        let first_backup = Feed::read(&backup_path);
+        // An existing backup is replaced
+        feed.add_url(&url, WebPage::default(), &[]);
+        feed.save(&options).unwrap();
+
+        let saved = Feed::read(&path);
+        let backup = Feed::read(&backup_path);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&backup_path);
+        assert!(no_backup);
+        assert_eq!(first_backup.unwrap().entries().len(), 0);
+        assert_eq!(saved.unwrap().entries().len(), 2);
+        assert_eq!(backup.unwrap().entries().len(), 1);
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let normalize = |url| normalize_url(&URI::try_from(url).unwrap());
+        assert_eq!(normalize("http://example.com"), "http://example.com/");
+        assert_eq!(
+            normalize("HTTPS://Example.COM:443/a/../b?z=1&a=2#top"),
+            "https://example.com/b?a=2&z=1"
+        );
+        assert_eq!(
+            normalize("http://example.com:8080/"),
+            "http://example.com:8080/"
+        );
+        assert_eq!(
+            normalize("http://example.com/path/?"),
+            "http://example.com/path/"
+        );
+    }
+
+    #[test]
+    fn test_add_url_if_new_normalized() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com").unwrap();
+        assert!(matches!(
+            feed.add_url_if_new(&url, WebPage::default(), &[]),
+            AddResult::Added(_)
+        ));
+        let href = feed.entries()[0].links()[0].href().to_string();
+        assert_eq!(href, "https://example.com/");
+
+        for duplicate in [
+            "https://example.com/",
+            "https://EXAMPLE.com",
+            "https://example.com:443/#fragment",
+        ] {
+            let url = URI::try_from(duplicate).unwrap();
+            assert_eq!(
+                feed.add_url_if_new(&url, WebPage::default(), &[]),
+                AddResult::Duplicate,
+                "{duplicate}"
+            );
+        }
+
+        // Entries stored before normalisation are still detected as duplicates
+        feed.feed.entries[0].links[0].href = "https://Example.com/?b=2&a=1".to_string();
+        let url = URI::try_from("https://example.com/?a=1&b=2").unwrap();
+        assert!(feed.contains_url(&url));
+    }
+
+    #[test]
+    fn test_add_url_long_description() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
+        let mut page = WebPage {
+            description: Some("word ".repeat(10_000)),
+            ..Default::default()
+        };
+        page.truncate_description(100);
+        feed.add_url(&url, page, &[]);
+        let summary = feed.entries()[0].summary().unwrap();
+        // Only the description is truncated, not the embed
+        assert!(summary.value.starts_with("<iframe"));
+        assert!(summary.value.contains("</iframe><div>word"));
+        assert!(summary.value.ends_with("…</div>"));
+        assert!(summary.value.len() < 1000);
+    }
+
+    #[test]
+    fn test_summary_html_description() {
+        let description = Some(r#"Fun <script>alert("pwned")</script> & games"#.to_string());
+        let url = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
+        let summary = summary_for_url(&url, description.clone(), None, None, SummaryFormat::Html);
+        assert!(!summary.value.contains("<script>"));
+        assert!(summary
+            .value
+            .ends_with("<div>Fun alert(&quot;pwned&quot;) &amp; games</div>"));
+
+        let url = URI::try_from("https://example.com/").unwrap();
+        let summary = summary_for_url(&url, description, None, None, SummaryFormat::Html);
+        assert_eq!(summary.value, r#"Fun alert("pwned") & games"#);
+    }
+
+    #[test]
+    fn test_summary_embed_html() {
+        let url = URI::try_from("https://example.com/video").unwrap();
+        let embed = Some(r#"<iframe src="https://example.com/embed/1"></iframe>"#.to_string());
+        let description = Some("A video".to_string());
+        let summary = summary_for_url(
+            &url,
+            description.clone(),
+            embed.clone(),
+            None,
+            SummaryFormat::Html,
+        );
+        assert_eq!(summary.r#type, atom::TextType::Html);
+        assert_eq!(
+            summary.value,
+            r#"<iframe src="https://example.com/embed/1"></iframe><div>A video</div>"#
+        );
+
+        // The oEmbed HTML is used instead of the built in YouTube embed
+        let youtube = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
+        let summary = summary_for_url(&youtube, None, embed.clone(), None, SummaryFormat::Html);
+        assert_eq!(summary.value, embed.clone().unwrap());
+
+        // Not used for plain text summaries
+        let summary = summary_for_url(&url, description, embed, None, SummaryFormat::Text);
+        assert_eq!(summary.value, "A video");
+    }
+
+    #[test]
+    fn test_summary_og_video() {
+        let url = URI::try_from("https://example.com/clip").unwrap();
+        let file = Video {
+            url: "https://cdn.example.com/clip.mp4".to_string(),
+            mime_type: None,
+        };
+        let summary = summary_for_url(&url, None, None, Some(&file), SummaryFormat::Html);
+        assert_eq!(
+            summary.value,
+            r#"<video src="https://cdn.example.com/clip.mp4" controls></video>"#
+        );
+
+        let player = Video {
+            url: "https://example.com/player?id=1&autoplay=0".to_string(),
+            mime_type: Some("text/html".to_string()),
+        };
+        let description = Some("A clip".to_string());
+        let summary = summary_for_url(&url, description, None, Some(&player), SummaryFormat::Html);
+        assert_eq!(
+            summary.value,
+            r#"<iframe width="560" height="315" src="https://example.com/player?id=1&amp;autoplay=0" frameborder="0" allowfullscreen></iframe><div>A clip</div>"#
+        );
+
+        // YouTube has its own player
+        let youtube = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
+        let summary = summary_for_url(&youtube, None, None, Some(&player), SummaryFormat::Html);
+        assert!(summary
+            .value
+            .contains("https://www.youtube.com/embed/u1wfCnRINkE"));
+
+        // Only HTTP(S) videos are embedded
+        let script = Video {
+            url: "javascript:alert(1)".to_string(),
+            mime_type: None,
+        };
+        let summary = summary_for_url(&url, None, None, Some(&script), SummaryFormat::Html);
+        assert_eq!(
+            summary.value,
+            r#"<a href="https://example.com/clip">https://example.com/clip</a>"#
+        );
+    }
+
+    #[test]
+    fn test_summary_text_format() {
+        let youtube = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
+        let description = Some("A <b>great</b> video".to_string());
+        let summary = summary_for_url(&youtube, description, None, None, SummaryFormat::Text);
+        assert_eq!(summary.r#type, atom::TextType::Text);
+        assert_eq!(summary.value, "A great video");
+
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let summary = summary_for_url(&url, None, None, None, SummaryFormat::Text);
+        assert_eq!(summary.r#type, atom::TextType::Text);
+        assert_eq!(summary.value, "https://example.com/post");
+
+        // The default is unchanged
+        let summary = summary_for_url(&url, None, None, None, SummaryFormat::Html);
+        assert_eq!(summary.r#type, atom::TextType::Html);
+    }
+
+    #[test]
+    fn test_add_url_image() {
+        let mut feed = Feed::generate_new("unused.xml");
+        let url = URI::try_from("https://example.com/post").unwrap();
+        let page = WebPage {
+            image: Some("https://example.com/images/post.PNG?size=large".to_string()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let entry = feed.feed.entries().last().unwrap();
+        let enclosure = entry
+            .links()
+            .iter()
+            .find(|link| link.rel() == "enclosure")
+            .unwrap();
+        assert_eq!(
+            enclosure.href(),
+            "https://example.com/images/post.PNG?size=large"
+        );
+        assert_eq!(enclosure.mime_type(), Some("image/png"));
+
+        // Relative or invalid image URLs are skipped
+        let page = WebPage {
+            image: Some("/images/post.jpg".to_string()),
+            ..Default::default()
+        };
+        feed.add_url(&url, page, &[]);
+        let entry = feed.feed.entries().last().unwrap();
+        assert_eq!(entry.links().len(), 1);
+    }
+
+    #[test]
+    fn test_image_enclosure_scheme() {
+        for image in [
+            "javascript:alert(1)",
+            "data:image/png;base64,iVBORw0KGgo=",
+            "file:///etc/passwd",
+            "ftp://example.com/post.png",
+        ] {
+            assert_eq!(image_enclosure(image), None, "{image}");
+        }
+        assert!(image_enclosure("http://example.com/post.png").is_some());
+    }
+
     #[test]
     fn test_video_id_direct() {
         let url = URI::try_from("https://www.youtube.com/watch?v=u1wfCnRINkE").unwrap();
@@ -0,0 +1,99 @@
+//! gzip ([RFC 1952]) compression and decompression of feed files.
+//!
+//! Uses [flate2] with its pure Rust backend.
+//!
+//! [RFC 1952]: https://www.rfc-editor.org/rfc/rfc1952
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Returns true if `path` has a `.gz` extension.
+///
+/// This alone determines whether a feed file is gzip compressed, when reading, saving, and serving
+/// it, so the file is always rewritten in the format it was read in.
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Compress `data` into a gzip stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // NOTE(unwrap): writing to a Vec can't fail
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompress a gzip stream.
+///
+/// The CRC and length in the trailer are checked, so truncated or corrupt data is an error.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) {
+        let compressed = compress(data);
+        assert!(compressed.starts_with(&[0x1f, 0x8b]));
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(b"");
+        roundtrip(b"a");
+        roundtrip(b"hello hello hello hello");
+        roundtrip(&[0; 100_000]);
+
+        let feed = include_bytes!("../tests/sample.xml");
+        roundtrip(feed);
+        assert!(compress(feed).len() < feed.len() / 3);
+
+        let random = crate::minrandom::random_numbers(1234)
+            .take(20_000)
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+        roundtrip(&random);
+    }
+
+    #[test]
+    fn test_decompress_dynamic() {
+        // Generated by zlib at level 9, which uses a dynamic Huffman block
+        let data = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xed, 0xcf, 0xc9, 0x11,
+            0xc2, 0x30, 0x10, 0x44, 0xd1, 0x3b, 0x51, 0x74, 0x04, 0x2e, 0xf6, 0x25, 0x01, 0x05,
+            0x80, 0x49, 0x40, 0x48, 0x63, 0x2c, 0x3c, 0xcc, 0x80, 0x24, 0x63, 0x9b, 0xe8, 0x51,
+            0x16, 0x54, 0x51, 0x3e, 0x77, 0xff, 0xc3, 0x33, 0x44, 0x9e, 0x27, 0x19, 0xe1, 0x94,
+            0x99, 0x5c, 0x4e, 0xe0, 0x20, 0x5d, 0x42, 0x56, 0x44, 0xb2, 0x1e, 0x1a, 0x31, 0xd8,
+            0xec, 0x5a, 0xb0, 0xcd, 0x14, 0x11, 0x04, 0x56, 0x70, 0xae, 0x6b, 0x34, 0x25, 0xac,
+            0x60, 0x7e, 0x9a, 0x5f, 0x5a, 0xc2, 0xab, 0x0f, 0xae, 0xc3, 0x35, 0xea, 0x20, 0x68,
+            0x74, 0xc4, 0xbd, 0x7f, 0x3c, 0x13, 0xf4, 0x5d, 0xee, 0xb9, 0xcc, 0x6c, 0x3f, 0x13,
+            0xbc, 0xde, 0xb0, 0x5c, 0xad, 0x37, 0xdb, 0xdd, 0xfe, 0x70, 0x3c, 0x55, 0x0b, 0x33,
+            0xa3, 0x67, 0xf4, 0xff, 0xa2, 0xbf, 0x96, 0x06, 0x08, 0x9c, 0xd4, 0x03, 0x00, 0x00,
+        ];
+        let expected = format!(
+            "{}The quick brown fox jumps over the lazy dog 0123456789.\n",
+            "Feedlynx collects links to read or watch later in an RSS feed. ".repeat(3)
+        )
+        .repeat(4);
+        assert_eq!(decompress(&data).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        assert!(decompress(b"<feed></feed>").is_err());
+        let mut data = compress(b"hello hello hello");
+        let len = data.len();
+        data[len - 8] ^= 0xff;
+        assert!(decompress(&data).is_err());
+        assert!(decompress(&data[..len - 8]).is_err());
+    }
+}
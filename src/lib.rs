@@ -1,15 +1,23 @@
 pub mod base62;
 mod feed;
+pub(crate) mod gzip;
 pub(crate) mod minrandom;
+mod multipart;
+mod qrcode;
 mod server;
 mod signals;
 pub mod webpage;
 
 use std::{fmt, io};
 
-pub use feed::Feed;
-pub use server::Server;
-pub use signals::SignalHandle;
+pub use feed::{
+    AddResult, EntryOptions, EntryOrder, Feed, SaveOptions, SummaryFormat, TrimOptions,
+    DEFAULT_UNTITLED,
+};
+pub use server::{
+    RunningServer, Server, ServerBuilder, DEFAULT_CACHE_MAX_AGE, DEFAULT_FEED, DEFAULT_READ_TIMEOUT,
+};
+pub use signals::{Signal, SignalHandle};
 
 pub const DEFAULT_ADDR: &str = "127.0.0.1";
 pub const DEFAULT_PORT: u16 = 8001;
@@ -51,6 +59,19 @@ macro_rules! embed {
     }};
 }
 
+impl Error {
+    /// Whether the feed file was read but is invalid, E.g. because it's truncated
+    pub fn is_corrupt(&self) -> bool {
+        match self {
+            Error::Feed(_) => true,
+            Error::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ),
+        }
+    }
+}
+
 impl PartialEq<str> for PrivateToken {
     fn eq(&self, other: &str) -> bool {
         self.0 == other
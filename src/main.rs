@@ -3,16 +3,30 @@ mod cli;
 use std::{
     env::{self, VarError},
     ffi::OsString,
+    fs,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     process::ExitCode,
     sync::Arc,
     thread,
+    time::Duration,
 };
 
+use atom_syndication::Person;
 use env_logger::Env;
+use feedlynx::webpage::{
+    self, FetchOptions, DEFAULT_FETCH_TIMEOUT, DEFAULT_MAX_PAGE_SIZE, DEFAULT_MAX_REDIRECTS,
+    DEFAULT_MAX_SUMMARY, DEFAULT_MIN_TITLE_LENGTH,
+};
 use feedlynx::{
-    base62::base62, webpage, Feed, FeedToken, PrivateToken, Server, DEFAULT_ADDR, DEFAULT_PORT,
+    base62::base62, AddResult, EntryOptions, EntryOrder, Feed, FeedToken, PrivateToken,
+    SaveOptions, ServerBuilder, Signal, SummaryFormat, TrimOptions, DEFAULT_ADDR,
+    DEFAULT_CACHE_MAX_AGE, DEFAULT_FEED, DEFAULT_PORT, DEFAULT_READ_TIMEOUT, DEFAULT_UNTITLED,
 };
-use log::{error, info, trace};
+use log::{error, info, trace, warn};
+use tinyjson::JsonValue;
+use uriparse::{Scheme, URI};
 
 use crate::cli::Command;
 
@@ -21,79 +35,165 @@ const ENV_PORT: &str = "FEEDLYNX_PORT";
 const ENV_PRIVATE_TOKEN: &str = "FEEDLYNX_PRIVATE_TOKEN";
 const ENV_FEED_TOKEN: &str = "FEEDLYNX_FEED_TOKEN";
 const ENV_LOG: &str = "FEEDLYNX_LOG";
+const ENV_LOG_FORMAT: &str = "FEEDLYNX_LOG_FORMAT";
+const ENV_FEED_ID: &str = "FEEDLYNX_FEED_ID";
+const ENV_PUBLIC_URL: &str = "FEEDLYNX_PUBLIC_URL";
+const ENV_TLS_CERT: &str = "FEEDLYNX_TLS_CERT";
+const ENV_TLS_KEY: &str = "FEEDLYNX_TLS_KEY";
+const ENV_TRUST_PROXY: &str = "FEEDLYNX_TRUST_PROXY";
+const ENV_CORS_ORIGIN: &str = "FEEDLYNX_CORS_ORIGIN";
+const ENV_ALLOWED_DOMAINS: &str = "FEEDLYNX_ALLOWED_DOMAINS";
+const ENV_BLOCKED_DOMAINS: &str = "FEEDLYNX_BLOCKED_DOMAINS";
+const ENV_ALLOW_PRIVATE_HOSTS: &str = "FEEDLYNX_ALLOW_PRIVATE_HOSTS";
+const ENV_MAX_PAGE_SIZE: &str = "FEEDLYNX_MAX_PAGE_SIZE";
+const ENV_FETCH_TIMEOUT: &str = "FEEDLYNX_FETCH_TIMEOUT";
+const ENV_MIN_TITLE_LENGTH: &str = "FEEDLYNX_MIN_TITLE_LENGTH";
+const ENV_MAX_REDIRECTS: &str = "FEEDLYNX_MAX_REDIRECTS";
+const ENV_OEMBED_HOSTS: &str = "FEEDLYNX_OEMBED_HOSTS";
+const ENV_MAX_SUMMARY: &str = "FEEDLYNX_MAX_SUMMARY";
+const ENV_SUMMARY_FORMAT: &str = "FEEDLYNX_SUMMARY_FORMAT";
+const ENV_ENTRY_ORDER: &str = "FEEDLYNX_ENTRY_ORDER";
+const ENV_UNTITLED_TEXT: &str = "FEEDLYNX_UNTITLED_TEXT";
+const ENV_PRETTY_FEED: &str = "FEEDLYNX_PRETTY_FEED";
+const ENV_KEEP_BACKUP: &str = "FEEDLYNX_KEEP_BACKUP";
+const ENV_REPAIR_FEED: &str = "FEEDLYNX_REPAIR_FEED";
+const ENV_FEEDS: &str = "FEEDLYNX_FEEDS";
+const ENV_ACCESS_LOG: &str = "FEEDLYNX_ACCESS_LOG";
+const ENV_READ_TIMEOUT: &str = "FEEDLYNX_READ_TIMEOUT";
+const ENV_CACHE_MAX_AGE: &str = "FEEDLYNX_CACHE_MAX_AGE";
+const ENV_ASYNC_FETCH: &str = "FEEDLYNX_ASYNC_FETCH";
+const ENV_BASE_PATH: &str = "FEEDLYNX_BASE_PATH";
+const ENV_PUBLIC_METRICS: &str = "FEEDLYNX_PUBLIC_METRICS";
+const ENV_SELFTEST: &str = "FEEDLYNX_SELFTEST";
+const ENV_SELFTEST_URL: &str = "FEEDLYNX_SELFTEST_URL";
+const ENV_SELFTEST_STRICT: &str = "FEEDLYNX_SELFTEST_STRICT";
+const ENV_AUTHOR_NAME: &str = "FEEDLYNX_AUTHOR_NAME";
+const ENV_AUTHOR_EMAIL: &str = "FEEDLYNX_AUTHOR_EMAIL";
+const ENV_AUTHOR_URI: &str = "FEEDLYNX_AUTHOR_URI";
+const ENV_FEED_RIGHTS: &str = "FEEDLYNX_FEED_RIGHTS";
+const ENV_FEED_LOGO: &str = "FEEDLYNX_FEED_LOGO";
+
+/// The minimum length of the private and feed tokens
+const MIN_TOKEN_LEN: usize = 32;
+
+/// Tokens with fewer distinct characters than this are warned about as weak
+const MIN_TOKEN_DISTINCT_CHARS: usize = 16;
 
 struct Config {
-    addr: String,
+    addrs: Vec<String>,
     port: u16,
     private_token: PrivateToken,
     feed_token: FeedToken,
+    feed_id: Option<String>,
+    public_url: Option<String>,
+    trust_proxy: bool,
+    cors_origins: Vec<String>,
+    allowed_domains: Vec<String>,
+    blocked_domains: Vec<String>,
+    fetch_options: FetchOptions,
+    save_options: SaveOptions,
+    /// Replace feeds that can't be read with new ones instead of failing to start
+    repair_feed: bool,
+    /// The URL fetched to check that pages can be fetched on startup, `None` to not check
+    selftest_url: Option<String>,
+    /// Fail to start if the self-test fails
+    selftest_strict: bool,
+    max_summary: usize,
+    entry_options: EntryOptions,
+    entry_order: EntryOrder,
+    /// Additional named feeds and their paths
+    feeds: Vec<(String, PathBuf)>,
+    access_log: bool,
+    read_timeout: Duration,
+    cache_max_age: Duration,
+    async_fetch: bool,
+    base_path: Option<String>,
+    public_metrics: bool,
+    /// The configured feed author, `None` to use the default
+    author: Option<Person>,
+    /// The copyright statement of the feeds, `None` to leave unchanged
+    rights: Option<String>,
+    /// The URL of the logo of the feeds, `None` to leave unchanged
+    logo: Option<String>,
+    /// The PEM certificate chain and private key to serve HTTPS with, `None` to serve HTTP
+    tls: Option<TlsFiles>,
 }
 
-fn main() -> ExitCode {
-    match env::var_os(ENV_LOG) {
-        None => env::set_var(ENV_LOG, "info"),
-        Some(_) => {}
-    }
-    env_logger::init_from_env(Env::new().filter(ENV_LOG));
+/// The contents of a PEM certificate chain and its private key
+type TlsFiles = (Vec<u8>, Vec<u8>);
 
-    let cmd = match cli::parse_args() {
-        Ok(cmd) => cmd,
+fn main() -> ExitCode {
+    let args = match cli::parse_args() {
+        Ok(args) => args,
         Err(err) => {
             eprintln!("Unable to parse arguments: {err}");
             return ExitCode::FAILURE;
         }
     };
 
-    let feed_path = match cmd {
-        Command::Serve(feed_path) => feed_path,
+    // An explicitly set FEEDLYNX_LOG takes precedence over --quiet and --verbose
+    if env::var_os(ENV_LOG).is_none() {
+        env::set_var(ENV_LOG, args.log_level.unwrap_or("info"))
+    }
+    if let Err(err) = init_logger() {
+        eprintln!("{err}");
+        return ExitCode::FAILURE;
+    }
+
+    let (feed_path, listen, port) = match args.command {
+        Command::Serve {
+            feed_path,
+            listen,
+            port,
+        } => (feed_path, listen, port),
         Command::GenToken => {
             generate_token();
             return ExitCode::SUCCESS;
         }
-        Command::Fetch(url) => {
-            fetch_webpage(url);
+        Command::Fetch {
+            url,
+            timeout,
+            max_redirects,
+        } => {
+            fetch_webpage(url, timeout, max_redirects);
             return ExitCode::SUCCESS;
         }
+        Command::Check(feed_path) => return check_feed(&feed_path),
+        Command::Trim(feed_path) => return trim_feed(&feed_path),
+        Command::Import(feed_path, urls_path) => return import_links(&feed_path, &urls_path),
         Command::Exit(code) => {
             return code;
         }
     };
 
-    // Create the feed file if it does not exist
-    if !feed_path.exists() {
-        info!("Creating initial feed at {}", feed_path.display());
-        let feed = Feed::generate_new(&feed_path);
-        match feed.save() {
-            Ok(()) => {}
-            Err(err) => {
-                eprintln!("Unable to save initial feed: {err}");
-                return ExitCode::FAILURE;
-            }
-        }
-    } else {
-        // Ensure existing feed can be read before starting the server
-        match Feed::read(&feed_path) {
-            Ok(_feed) => {}
-            Err(err) => {
-                eprintln!("Unable to read feed at {}: {err}", feed_path.display());
-                return ExitCode::FAILURE;
-            }
-        }
-    }
-
-    let config = match read_config() {
+    let config = match read_config(listen.as_deref(), port) {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Unable to read configuration: {err}");
-            eprintln!(
-                "{} and {} must both be set to a 32 character string",
-                ENV_PRIVATE_TOKEN, ENV_FEED_TOKEN
-            );
-            eprintln!("Generate tokens with: {} gen-token", env!("CARGO_BIN_NAME"));
             return ExitCode::FAILURE;
         }
     };
 
+    // Ensure the feeds exist and can be read before starting the server
+    if let Err(err) = prepare_feed(&feed_path, None, &config) {
+        eprintln!("{err}");
+        return ExitCode::FAILURE;
+    }
+    for (name, path) in &config.feeds {
+        if let Err(err) = prepare_feed(path, Some(name), &config) {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Surface network problems now rather than when the first link is added
+    if let Some(url) = &config.selftest_url {
+        if !selftest(url, &config.fetch_options) && config.selftest_strict {
+            eprintln!("Self-test failed and {ENV_SELFTEST_STRICT} is set");
+            return ExitCode::FAILURE;
+        }
+    }
+
     // This sets the signal mask, which has to happen before the server starts its threads
     // so that they inherit the mask
     let signals = match feedlynx::SignalHandle::new() {
@@ -104,17 +204,51 @@ fn main() -> ExitCode {
         }
     };
 
-    let server = match Server::new(
-        (config.addr.clone(), config.port),
-        config.private_token,
-        config.feed_token,
-        feed_path,
-    ) {
-        Ok(server) => Arc::new(server),
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let builder = config
+        .addrs
+        .iter()
+        .fold(ServerBuilder::new(feed_path), |builder, addr| {
+            builder.listen(addr.as_str(), config.port)
+        })
+        .private_token(config.private_token)
+        .feed_token(config.feed_token)
+        .fetch_options(config.fetch_options)
+        .cors_origins(config.cors_origins);
+    #[cfg(feature = "tls")]
+    let builder = match config.tls {
+        Some((certificate, private_key)) => builder.tls(certificate, private_key),
+        None => builder,
+    };
+    let server = match builder.build() {
+        Ok(server) => Arc::new(
+            server
+                .with_public_url(config.public_url)
+                .with_trust_proxy(config.trust_proxy)
+                .with_allowed_domains(config.allowed_domains)
+                .with_blocked_domains(config.blocked_domains)
+                .with_save_options(config.save_options)
+                .with_max_summary(config.max_summary)
+                .with_entry_options(config.entry_options)
+                .with_entry_order(config.entry_order)
+                .with_feeds(config.feeds)
+                .with_access_log(config.access_log)
+                .with_read_timeout(config.read_timeout)
+                .with_cache_max_age(config.cache_max_age)
+                .with_async_fetch(config.async_fetch)
+                .with_base_path(config.base_path)
+                .with_public_metrics(config.public_metrics),
+        ),
         Err(err) => {
             eprintln!(
-                "Unable to start http server on {}:{}: {}",
-                config.addr, config.port, err
+                "Unable to start http server on {} port {}: {}",
+                config.addrs.join(", "),
+                config.port,
+                err
             );
             return ExitCode::FAILURE;
         }
@@ -125,19 +259,30 @@ fn main() -> ExitCode {
     let join_handle = thread::Builder::new()
         .name("signal-handler".to_string())
         .spawn(move || {
-            trace!("waiting for signals...");
-            match signals.block_until_signalled() {
-                Ok(()) => trace!("signalled!"),
-                Err(err) => error!("Waiting for signals failed: {err}"),
+            loop {
+                trace!("waiting for signals...");
+                match signals.block_until_signalled() {
+                    Ok(Signal::Trim) => {
+                        info!("Trimming feeds");
+                        server2.trim_feeds();
+                    }
+                    Ok(Signal::Shutdown) => {
+                        trace!("signalled!");
+                        break;
+                    }
+                    Err(err) => {
+                        error!("Waiting for signals failed: {err}");
+                        break;
+                    }
+                }
             }
             server2.shutdown();
         })
         .unwrap(); // NOTE(unwrap): if thread fails to spawn panic seems reasonable
 
-    info!(
-        "HTTP server running on: http://{}:{}",
-        config.addr, config.port
-    );
+    for addr in server.server_addrs() {
+        info!("HTTP server running on: {scheme}://{}", addr);
+    }
     server.handle_requests();
     trace!("server finished handling requests");
 
@@ -147,53 +292,665 @@ fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
-fn read_config() -> Result<Config, String> {
-    let server_addr = env::var(ENV_ADDRESS).unwrap_or_else(|_| String::from(DEFAULT_ADDR));
-    let server_port = env::var(ENV_PORT)
-        .ok()
-        .and_then(|port| port.parse::<u16>().ok())
+/// Initialise the logger, writing JSON lines if `FEEDLYNX_LOG_FORMAT` is `json`.
+fn init_logger() -> Result<(), String> {
+    let mut builder = env_logger::Builder::from_env(Env::new().filter(ENV_LOG));
+    match env::var(ENV_LOG_FORMAT).as_deref() {
+        Err(_) | Ok("") | Ok("text") => {}
+        Ok("json") => {
+            builder.format(|buf, record| {
+                let string = |value: String| {
+                    // NOTE(unwrap): stringifying a string can't fail
+                    JsonValue::String(value).stringify().unwrap()
+                };
+                writeln!(
+                    buf,
+                    r#"{{"timestamp":{},"level":{},"target":{},"message":{}}}"#,
+                    string(buf.timestamp().to_string()),
+                    string(record.level().to_string()),
+                    string(record.target().to_string()),
+                    string(record.args().to_string()),
+                )
+            });
+        }
+        Ok(other) => {
+            return Err(format!(
+                "{ENV_LOG_FORMAT} must be text or json, not '{other}'"
+            ))
+        }
+    }
+    builder.init();
+    Ok(())
+}
+
+/// Read the configuration from the environment.
+///
+/// `listen` and `port` are from the command line and take precedence over the environment.
+fn read_config(listen: Option<&str>, port: Option<u16>) -> Result<Config, String> {
+    let listen = listen.map(parse_listen).transpose()?;
+    let mut server_addrs = match &listen {
+        Some((addr, _)) => vec![addr.clone()],
+        None => read_list(ENV_ADDRESS),
+    }
+    .into_iter()
+    .map(|addr| {
+        addr.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string()
+    })
+    .filter(|addr| !addr.is_empty())
+    .collect::<Vec<_>>();
+    if server_addrs.is_empty() {
+        server_addrs.push(String::from(DEFAULT_ADDR));
+    }
+    let server_port = port
+        .or_else(|| listen.and_then(|(_, port)| port))
+        .or_else(|| {
+            env::var(ENV_PORT)
+                .ok()
+                .and_then(|port| port.parse::<u16>().ok())
+        })
         .unwrap_or(DEFAULT_PORT);
 
-    let private_token = read_token(ENV_PRIVATE_TOKEN).map(PrivateToken)?;
-    let feed_token = read_token(ENV_FEED_TOKEN).map(FeedToken)?;
+    let private_token = read_token(ENV_PRIVATE_TOKEN)
+        .map(PrivateToken)
+        .map_err(token_hint)?;
+    let feed_token = read_token(ENV_FEED_TOKEN)
+        .map(FeedToken)
+        .map_err(token_hint)?;
+    let feed_id = env::var(ENV_FEED_ID)
+        .ok()
+        .filter(|id| !id.is_empty())
+        .map(|id| match URI::try_from(id.as_str()) {
+            Ok(_) => Ok(id),
+            Err(err) => Err(format!("{ENV_FEED_ID} must be a valid URI: {err}")),
+        })
+        .transpose()?;
+    let author = read_author()?;
+    let rights = env::var(ENV_FEED_RIGHTS)
+        .ok()
+        .filter(|rights| !rights.is_empty());
+    let logo = env::var(ENV_FEED_LOGO)
+        .ok()
+        .filter(|logo| !logo.is_empty())
+        .map(|logo| match URI::try_from(logo.as_str()) {
+            Ok(_) => Ok(logo),
+            Err(err) => Err(format!("{ENV_FEED_LOGO} must be a valid URI: {err}")),
+        })
+        .transpose()?;
+    let entry_options = read_entry_options()?;
+    let entry_order = match env::var(ENV_ENTRY_ORDER).as_deref() {
+        Err(_) | Ok("") | Ok("newest") => EntryOrder::Newest,
+        Ok("oldest") => EntryOrder::Oldest,
+        Ok(other) => {
+            return Err(format!(
+                "{ENV_ENTRY_ORDER} must be newest or oldest, not '{other}'"
+            ))
+        }
+    };
+    let feeds = read_list(ENV_FEEDS)
+        .into_iter()
+        .map(|item| parse_feed(&item))
+        .collect::<Result<Vec<_>, _>>()?;
+    let public_url = env::var(ENV_PUBLIC_URL).ok().filter(|url| !url.is_empty());
+    let tls = read_tls()?;
 
     Ok(Config {
-        addr: server_addr,
+        addrs: server_addrs,
         port: server_port,
         private_token,
         feed_token,
+        feed_id,
+        public_url,
+        trust_proxy: read_flag(ENV_TRUST_PROXY),
+        cors_origins: read_list(ENV_CORS_ORIGIN),
+        allowed_domains: read_list(ENV_ALLOWED_DOMAINS),
+        blocked_domains: read_list(ENV_BLOCKED_DOMAINS),
+        fetch_options: read_fetch_options(),
+        save_options: read_save_options(),
+        max_summary: read_max_summary(),
+        entry_options,
+        entry_order,
+        feeds,
+        access_log: read_flag(ENV_ACCESS_LOG),
+        read_timeout: env::var(ENV_READ_TIMEOUT)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_READ_TIMEOUT),
+        cache_max_age: env::var(ENV_CACHE_MAX_AGE)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CACHE_MAX_AGE),
+        async_fetch: read_flag(ENV_ASYNC_FETCH),
+        base_path: env::var(ENV_BASE_PATH).ok(),
+        public_metrics: read_flag(ENV_PUBLIC_METRICS),
+        repair_feed: read_flag(ENV_REPAIR_FEED),
+        selftest_url: read_flag(ENV_SELFTEST).then(|| {
+            env::var(ENV_SELFTEST_URL)
+                .ok()
+                .filter(|url| !url.is_empty())
+                .unwrap_or_else(|| env!("CARGO_PKG_HOMEPAGE").to_string())
+        }),
+        selftest_strict: read_flag(ENV_SELFTEST_STRICT),
+        author,
+        rights,
+        logo,
+        tls,
     })
 }
 
+/// Read the feed author from the environment.
+///
+/// Returns `None` if none of the author variables are set. If only the email or URI is set the
+/// name defaults to the package name.
+fn read_author() -> Result<Option<Person>, String> {
+    let read = |name| env::var(name).ok().filter(|value| !value.is_empty());
+    let name = read(ENV_AUTHOR_NAME);
+    let email = read(ENV_AUTHOR_EMAIL);
+    let uri = read(ENV_AUTHOR_URI);
+    if name.is_none() && email.is_none() && uri.is_none() {
+        return Ok(None);
+    }
+    if let Some(uri) = &uri {
+        if let Err(err) = URI::try_from(uri.as_str()) {
+            return Err(format!("{ENV_AUTHOR_URI} must be a valid URI: {err}"));
+        }
+    }
+    Ok(Some(Person {
+        name: name.unwrap_or_else(|| Feed::default_author().name),
+        email,
+        uri,
+    }))
+}
+
+/// Parse the `--listen` address into the host and port, if present.
+///
+/// The address may be a host, IP address, or either with a port, E.g. `0.0.0.0:9000`,
+/// `[::1]:9000` or `localhost`. An empty host, like in `:9000`, uses the default address.
+fn parse_listen(listen: &str) -> Result<(String, Option<u16>), String> {
+    if let Ok(addr) = listen.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), Some(addr.port())));
+    }
+    let unbracketed = listen.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return Ok((ip.to_string(), None));
+    }
+    match listen.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => Ok((host.to_string(), Some(port))),
+            Err(_) => Err(format!("invalid port in listen address: {listen}")),
+        },
+        None => Ok((listen.to_string(), None)),
+    }
+}
+
+/// Parse a `name=path` item from the list of named feeds
+fn parse_feed(item: &str) -> Result<(String, PathBuf), String> {
+    let Some((name, path)) = item.split_once('=') else {
+        return Err(format!("{ENV_FEEDS} items must be name=path: {item}"));
+    };
+    let name = name.trim();
+    let path = path.trim();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "{ENV_FEEDS} feed names must only contain letters, numbers, '-' and '_': {name}"
+        ));
+    }
+    if name == DEFAULT_FEED {
+        return Err(format!(
+            "{ENV_FEEDS} feed name '{name}' is reserved for FEED_PATH"
+        ));
+    }
+    if path.is_empty() {
+        return Err(format!("{ENV_FEEDS} feed '{name}' is missing a path"));
+    }
+    Ok((name.to_string(), PathBuf::from(path)))
+}
+
+/// Apply the configured author, rights, and logo to `feed`, returning `true` if it changed.
+fn update_metadata(feed: &mut Feed, config: &Config) -> bool {
+    let mut changed = false;
+    if let Some(author) = &config.author {
+        changed |= feed.set_author(author.clone());
+    }
+    if let Some(rights) = &config.rights {
+        changed |= feed.set_rights(rights);
+    }
+    if let Some(logo) = &config.logo {
+        changed |= feed.set_logo(logo);
+    }
+    changed
+}
+
+/// Create the feed at `feed_path` if it does not exist, otherwise ensure it can be read.
+///
+/// `name` is the name of the feed, `None` for the default feed.
+fn prepare_feed(feed_path: &Path, name: Option<&str>, config: &Config) -> Result<(), String> {
+    // FEEDLYNX_FEED_ID only applies to the default feed
+    let feed_id = match name {
+        Some(_) => None,
+        None => config.feed_id.as_deref(),
+    };
+
+    // Create the feed file if it does not exist
+    if !feed_path.exists() && !Feed::backup_path(feed_path).exists() {
+        info!("Creating initial feed at {}", feed_path.display());
+        create_feed(feed_path, feed_id, name, config)
+    } else {
+        match Feed::read(feed_path).or_else(|err| restore_backup(feed_path, err)) {
+            Ok(mut feed) => {
+                match feed_id {
+                    Some(feed_id) if feed.id() != feed_id => warn!(
+                        "Feed id {} does not match {}: {}",
+                        feed.id(),
+                        ENV_FEED_ID,
+                        feed_id
+                    ),
+                    _ => {}
+                }
+                if update_metadata(&mut feed, config) {
+                    info!("Updating metadata of feed at {}", feed_path.display());
+                    feed.save(&config.save_options)
+                        .map_err(|err| format!("Unable to save feed: {err}"))
+                } else {
+                    Ok(())
+                }
+            }
+            Err(err) if config.repair_feed && err.is_corrupt() => {
+                let corrupt_path = corrupt_path(feed_path);
+                error!(
+                    "Unable to read feed at {}: {err}. Moving it to {} and creating a new feed \
+                     because {} is set",
+                    feed_path.display(),
+                    corrupt_path.display(),
+                    ENV_REPAIR_FEED
+                );
+                fs::rename(feed_path, &corrupt_path).map_err(|err| {
+                    format!("Unable to move feed to {}: {err}", corrupt_path.display())
+                })?;
+                create_feed(feed_path, feed_id, name, config)
+            }
+            Err(err) => Err(format!(
+                "Unable to read feed at {}: {err}",
+                feed_path.display()
+            )),
+        }
+    }
+}
+
+/// Fetch `url` to check that pages can be fetched, returning `true` if it was.
+fn selftest(url: &str, options: &FetchOptions) -> bool {
+    info!("Self-test: fetching {url}");
+    match webpage::fetch(url, options) {
+        Ok(page) => {
+            info!(
+                "Self-test passed: fetched {url} with title {:?}",
+                page.title
+            );
+            true
+        }
+        Err(err) => {
+            warn!("Self-test failed: unable to fetch {url}: {err}");
+            false
+        }
+    }
+}
+
+/// Create a new, empty feed at `feed_path` with the id `feed_id`, or a random one.
+fn create_feed(
+    feed_path: &Path,
+    feed_id: Option<&str>,
+    name: Option<&str>,
+    config: &Config,
+) -> Result<(), String> {
+    let mut feed = Feed::generate_new(feed_path);
+    match (feed_id, name) {
+        (Some(feed_id), _) => feed.set_id(feed_id),
+        (None, None) => warn!(
+            "{} is not set, generated random feed id: {}. Set {} to this value to keep the \
+             same id if the feed file is recreated.",
+            ENV_FEED_ID,
+            feed.id(),
+            ENV_FEED_ID
+        ),
+        (None, Some(_)) => {}
+    }
+    update_metadata(&mut feed, config);
+    feed.save(&config.save_options)
+        .map_err(|err| format!("Unable to save initial feed: {err}"))
+}
+
+/// The path a corrupt feed at `path` is moved to when repairing it, E.g. `feed.xml.corrupt`.
+fn corrupt_path(path: &Path) -> PathBuf {
+    let mut corrupt_path = path.as_os_str().to_owned();
+    corrupt_path.push(".corrupt");
+    PathBuf::from(corrupt_path)
+}
+
+fn read_fetch_options() -> FetchOptions {
+    FetchOptions {
+        allow_private_hosts: read_flag(ENV_ALLOW_PRIVATE_HOSTS),
+        max_page_size: env::var(ENV_MAX_PAGE_SIZE)
+            .ok()
+            .and_then(|size| size.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_PAGE_SIZE),
+        timeout: env::var(ENV_FETCH_TIMEOUT)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_FETCH_TIMEOUT),
+        min_title_length: env::var(ENV_MIN_TITLE_LENGTH)
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(DEFAULT_MIN_TITLE_LENGTH),
+        max_redirects: env::var(ENV_MAX_REDIRECTS)
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(DEFAULT_MAX_REDIRECTS),
+        oembed_hosts: read_list(ENV_OEMBED_HOSTS),
+        user_agent: webpage::default_user_agent(),
+    }
+}
+
+fn read_max_summary() -> usize {
+    env::var(ENV_MAX_SUMMARY)
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(DEFAULT_MAX_SUMMARY)
+}
+
+fn read_entry_options() -> Result<EntryOptions, String> {
+    let summary_format = match env::var(ENV_SUMMARY_FORMAT).as_deref() {
+        Err(_) | Ok("") | Ok("html") => SummaryFormat::Html,
+        Ok("text") => SummaryFormat::Text,
+        Ok(other) => {
+            return Err(format!(
+                "{ENV_SUMMARY_FORMAT} must be text or html, not '{other}'"
+            ))
+        }
+    };
+    let untitled = env::var(ENV_UNTITLED_TEXT)
+        .ok()
+        .filter(|text| !text.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_UNTITLED.to_string());
+    Ok(EntryOptions {
+        summary_format,
+        untitled,
+    })
+}
+
+/// Read the certificate and private key files to serve HTTPS with, if they're set.
+fn read_tls() -> Result<Option<TlsFiles>, String> {
+    let path = |name| env::var_os(name).filter(|path| !path.is_empty());
+    let (cert_path, key_path) = match (path(ENV_TLS_CERT), path(ENV_TLS_KEY)) {
+        (None, None) => return Ok(None),
+        (Some(cert_path), Some(key_path)) => (PathBuf::from(cert_path), PathBuf::from(key_path)),
+        _ => {
+            return Err(format!(
+                "{ENV_TLS_CERT} and {ENV_TLS_KEY} must be set together"
+            ))
+        }
+    };
+    if !cfg!(feature = "tls") {
+        return Err(format!(
+            "{ENV_TLS_CERT} requires feedlynx to be built with the tls feature"
+        ));
+    }
+    let read = |path: PathBuf| {
+        fs::read(&path).map_err(|err| format!("Unable to read {}: {err}", path.display()))
+    };
+    Ok(Some((read(cert_path)?, read(key_path)?)))
+}
+
+fn read_save_options() -> SaveOptions {
+    SaveOptions {
+        pretty: read_flag(ENV_PRETTY_FEED),
+        keep_backup: read_flag(ENV_KEEP_BACKUP),
+    }
+}
+
+/// Read a boolean environment variable, which is enabled when set to `1` or `true`
+fn read_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Read a comma-separated list from the environment variable `name`, skipping empty items
+fn read_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 fn read_token(name: &str) -> Result<String, String> {
     let token = env::var(name).map_err(|err| match err {
         VarError::NotPresent => format!("{} environment variable is not set", name),
         VarError::NotUnicode(_) => format!("{} environment variable is not valid utf-8", name),
     })?;
 
-    if token.len() < 32 {
-        return Err(format!("{} is too short", name));
+    let len = token.chars().count();
+    if len < MIN_TOKEN_LEN {
+        return Err(format!(
+            "{name} is too short, it is {len} characters but must be at least {MIN_TOKEN_LEN}"
+        ));
+    }
+
+    // Tokens from gen-token are random base62 so they'll almost always have many more distinct
+    // characters than this. Fewer suggests a guessable token like `aaaa…` or `1234…`.
+    let mut chars = token.chars().collect::<Vec<_>>();
+    chars.sort_unstable();
+    chars.dedup();
+    if chars.len() < MIN_TOKEN_DISTINCT_CHARS {
+        warn!(
+            "{name} only has {} different characters and may be easy to guess. \
+             Generate a random token with: {} gen-token",
+            chars.len(),
+            env!("CARGO_BIN_NAME")
+        );
     }
 
     Ok(token)
 }
 
+/// Add a hint about how to set the tokens to a token error
+fn token_hint(err: String) -> String {
+    format!(
+        "{err}\n{} and {} must both be set to a string of at least {MIN_TOKEN_LEN} characters\nGenerate tokens with: {} gen-token",
+        ENV_PRIVATE_TOKEN,
+        ENV_FEED_TOKEN,
+        env!("CARGO_BIN_NAME")
+    )
+}
+
 /// Generate and print a base62 encoded token
 fn generate_token() {
     println!("{}", base62::<32>());
 }
 
-fn fetch_webpage(url: Option<OsString>) {
+/// Restore the feed at `feed_path` from its backup after it failed to be read with `err`
+fn restore_backup(feed_path: &Path, err: feedlynx::Error) -> Result<Feed, feedlynx::Error> {
+    let backup_path = Feed::backup_path(feed_path);
+    if !backup_path.exists() {
+        return Err(err);
+    }
+
+    warn!(
+        "Unable to read feed at {}: {err}. Restoring backup from {}",
+        feed_path.display(),
+        backup_path.display()
+    );
+    Feed::read(&backup_path)?;
+    fs::copy(&backup_path, feed_path)?;
+    Feed::read(feed_path)
+}
+
+/// Read the feed at `feed_path` and report whether it is valid
+fn check_feed(feed_path: &Path) -> ExitCode {
+    match Feed::read(feed_path) {
+        Ok(feed) => {
+            let count = feed.entries().len();
+            println!(
+                "{}: ok, {} {}",
+                feed_path.display(),
+                count,
+                if count == 1 { "entry" } else { "entries" }
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}: unable to read feed: {}", feed_path.display(), err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Trim old entries from the feed at `feed_path` and save it
+fn trim_feed(feed_path: &Path) -> ExitCode {
+    let mut feed = match Feed::read(feed_path) {
+        Ok(feed) => feed,
+        Err(err) => {
+            eprintln!("{}: unable to read feed: {}", feed_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let before = feed.entries().len();
+    feed.trim_entries(&TrimOptions::default());
+    let removed = before - feed.entries().len();
+    if removed > 0 {
+        if let Err(err) = feed.save(&read_save_options()) {
+            eprintln!("{}: unable to save feed: {}", feed_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    println!(
+        "{}: removed {} {}",
+        feed_path.display(),
+        removed,
+        if removed == 1 { "entry" } else { "entries" }
+    );
+    ExitCode::SUCCESS
+}
+
+/// Add the links listed in the file at `urls_path` to the feed at `feed_path` and save it
+///
+/// The file contains one URL per line. Blank lines and lines starting with `#` are skipped.
+fn import_links(feed_path: &Path, urls_path: &Path) -> ExitCode {
+    let urls = match fs::read_to_string(urls_path) {
+        Ok(urls) => urls,
+        Err(err) => {
+            eprintln!("{}: unable to read links: {}", urls_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let entry_options = match read_entry_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut feed = match Feed::read(feed_path) {
+        Ok(feed) => feed,
+        Err(err) => {
+            eprintln!("{}: unable to read feed: {}", feed_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    feed.set_entry_options(entry_options);
+
+    let fetch_options = read_fetch_options();
+    let max_summary = read_max_summary();
+    let (mut added, mut skipped, mut failed) = (0, 0, 0);
+    for line in urls.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let url = match URI::try_from(line) {
+            Ok(url) if matches!(url.scheme(), Scheme::HTTP | Scheme::HTTPS) => url,
+            _ => {
+                eprintln!("{line}: invalid URL");
+                failed += 1;
+                continue;
+            }
+        };
+        // Avoid fetching pages that are already in the feed
+        if feed.contains_url(&url) {
+            skipped += 1;
+            continue;
+        }
+        let mut page = match webpage::fetch(line, &fetch_options) {
+            Ok(page) => page,
+            Err(err) => {
+                eprintln!("{line}: unable to fetch page: {err}");
+                failed += 1;
+                continue;
+            }
+        };
+        if max_summary > 0 {
+            page.truncate_description(max_summary);
+        }
+        match feed.add_url_if_new(&url, page, &[]) {
+            AddResult::Added(_) => added += 1,
+            AddResult::Duplicate => skipped += 1,
+        }
+    }
+
+    if added > 0 {
+        feed.trim_entries(&TrimOptions::default());
+        if let Err(err) = feed.save(&read_save_options()) {
+            eprintln!("{}: unable to save feed: {}", feed_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    println!(
+        "{}: added {}, skipped {}, failed {}",
+        feed_path.display(),
+        added,
+        skipped,
+        failed
+    );
+    ExitCode::SUCCESS
+}
+
+/// Fetch `url` and print the metadata found, `timeout` overrides FEEDLYNX_FETCH_TIMEOUT
+fn fetch_webpage(url: Option<OsString>, timeout: Option<Duration>, max_redirects: Option<usize>) {
     let Some(url) = url.as_ref().and_then(|os| os.to_str()) else {
         error!("missing url");
         return;
     };
 
-    match webpage::fetch(url) {
+    let mut options = read_fetch_options();
+    if let Some(timeout) = timeout {
+        options.timeout = timeout;
+    }
+    if let Some(max_redirects) = max_redirects {
+        options.max_redirects = max_redirects;
+    }
+    match webpage::fetch(url, &options) {
         Ok(page) => {
             println!(
-                "title: {:?}\ndescription: {:?}",
-                page.title, page.description
+                "title: {:?}\ndescription: {:?}\nauthor: {:?}\ntype: {:?}\npublished: {:?}\nlow confidence: {}",
+                page.title,
+                page.description,
+                page.author,
+                page.og_type,
+                page.published,
+                page.low_confidence
             )
         }
         Err(err) => {
@@ -0,0 +1,147 @@
+//! Minimal `multipart/form-data` ([RFC 7578]) parsing.
+//!
+//! Only the simple text fields of a form are supported. Parts with a filename, I.e. uploaded
+//! files, are skipped.
+//!
+//! [RFC 7578]: https://www.rfc-editor.org/rfc/rfc7578
+
+/// Parse the text fields of the multipart `body`, with parts separated by `boundary`.
+///
+/// Returns the name and value of each field in order, or `None` if the body is malformed.
+pub fn parse(body: &[u8], boundary: &str) -> Option<Vec<(String, String)>> {
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+
+    // Anything before the first delimiter is a preamble and is ignored
+    let start = find(body, delimiter)?;
+    let mut rest = &body[start + delimiter.len()..];
+    let mut fields = Vec::new();
+    loop {
+        // The last delimiter is followed by `--`
+        if rest.starts_with(b"--") {
+            return Some(fields);
+        }
+        rest = strip_line_ending(skip_whitespace(rest))?;
+
+        // Each part ends at the line break before the next delimiter
+        let end = find(rest, delimiter)?;
+        let part = strip_trailing_line_ending(&rest[..end]);
+        rest = &rest[end + delimiter.len()..];
+
+        if let Some(field) = parse_part(part)? {
+            fields.push(field);
+        }
+    }
+}
+
+/// Parse a part into its field name and value, or `None` in the `Option` if it isn't a text field.
+fn parse_part(part: &[u8]) -> Option<Option<(String, String)>> {
+    let (headers, value) = match find(part, b"\r\n\r\n") {
+        Some(pos) => (&part[..pos], &part[pos + 4..]),
+        None => {
+            let pos = find(part, b"\n\n")?;
+            (&part[..pos], &part[pos + 2..])
+        }
+    };
+    let headers = std::str::from_utf8(headers).ok()?;
+
+    let mut name = None;
+    for header in headers.lines() {
+        let Some((field, header_value)) = header.split_once(':') else {
+            continue;
+        };
+        if !field.trim().eq_ignore_ascii_case("content-disposition") {
+            continue;
+        }
+        for param in header_value.split(';').skip(1) {
+            let Some((key, param_value)) = param.split_once('=') else {
+                continue;
+            };
+            let param_value = param_value.trim().trim_matches('"');
+            match key.trim().to_ascii_lowercase().as_str() {
+                "name" => name = Some(param_value.to_string()),
+                "filename" => return Some(None),
+                _ => {}
+            }
+        }
+    }
+
+    let value = String::from_utf8(value.to_vec()).ok()?;
+    Some(name.map(|name| (name, value)))
+}
+
+/// The position of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Skip the whitespace allowed after a delimiter
+fn skip_whitespace(data: &[u8]) -> &[u8] {
+    let len = data
+        .iter()
+        .take_while(|&&byte| byte == b' ' || byte == b'\t')
+        .count();
+    &data[len..]
+}
+
+fn strip_line_ending(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(b"\r\n")
+        .or_else(|| data.strip_prefix(b"\n"))
+}
+
+fn strip_trailing_line_ending(data: &[u8]) -> &[u8] {
+    data.strip_suffix(b"\r\n")
+        .or_else(|| data.strip_suffix(b"\n"))
+        .unwrap_or(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let body = "preamble\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"url\"\r\n\
+            \r\n\
+            https://example.com/\r\n\
+            --XyZ\r\n\
+            content-disposition: form-data; name=title\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            \r\n\
+            Multiple\r\nLines\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            \r\n\
+            file contents\r\n\
+            --XyZ--\r\n";
+        let fields = parse(body.as_bytes(), "XyZ").unwrap();
+        assert_eq!(
+            fields,
+            [
+                ("url".to_string(), "https://example.com/".to_string()),
+                ("title".to_string(), "Multiple\r\nLines".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_value() {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"tags\"\r\n\r\n\r\n--b--";
+        let fields = parse(body.as_bytes(), "b").unwrap();
+        assert_eq!(fields, [("tags".to_string(), String::new())]);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        // Missing closing delimiter
+        let body = "--b\r\nContent-Disposition: form-data; name=\"url\"\r\n\r\nvalue";
+        assert_eq!(parse(body.as_bytes(), "b"), None);
+        // Wrong boundary
+        let body = "--b\r\nContent-Disposition: form-data; name=\"url\"\r\n\r\nvalue\r\n--b--";
+        assert_eq!(parse(body.as_bytes(), "other"), None);
+    }
+}
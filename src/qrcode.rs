@@ -0,0 +1,545 @@
+//! A small QR Code encoder for rendering URLs as SVG.
+//!
+//! Only what's needed to encode a URL is implemented: byte mode, error correction level M, and
+//! automatic selection of the version (size) and mask. The approach follows the [QR Code
+//! generator library] by Project Nayuki.
+//!
+//! [QR Code generator library]: https://www.nayuki.io/page/qr-code-generator-library
+
+use std::fmt::Write;
+
+/// Error correction codewords per block for level M, indexed by version
+const ECC_CODEWORDS_PER_BLOCK: [u8; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// Number of error correction blocks for level M, indexed by version
+const NUM_ERROR_CORRECTION_BLOCKS: [u8; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// The format information bits for level M
+const ECC_LEVEL_M: u32 = 0;
+
+/// Light modules around the symbol, as required by the specification
+const QUIET_ZONE: usize = 4;
+
+/// An encoded QR Code: a square grid of dark (`true`) and light modules.
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that fits, or `None` if it's too long.
+    pub fn encode(data: &[u8]) -> Option<QrCode> {
+        let (version, data_codewords) = (1..=40).find_map(|version| {
+            let capacity = num_data_codewords(version) * 8;
+            let bits = 4 + char_count_bits(version) + data.len() * 8;
+            (bits <= capacity).then_some((version, capacity / 8))
+        })?;
+
+        // Mode indicator, character count, and the data itself
+        let mut bits = BitBuffer::default();
+        bits.append(0b0100, 4);
+        bits.append(data.len() as u32, char_count_bits(version));
+        data.iter().for_each(|&byte| bits.append(byte.into(), 8));
+
+        // Terminator, then pad to a byte and fill the remaining capacity with pad bytes
+        let capacity = data_codewords * 8;
+        bits.append(0, (capacity - bits.len()).min(4));
+        bits.append(0, (8 - bits.len() % 8) % 8);
+        for &pad in [0xEC, 0x11].iter().cycle() {
+            if bits.len() >= capacity {
+                break;
+            }
+            bits.append(pad, 8);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(version, &bits.to_bytes()));
+
+        // Use the mask with the lowest penalty
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(mask);
+                let penalty = qr.penalty_score();
+                qr.apply_mask(mask); // XOR is its own inverse
+                penalty
+            })
+            .unwrap_or(0);
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+        Some(qr)
+    }
+
+    /// Whether the module at `x`, `y` is dark.
+    pub fn module(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Render the code as an SVG image, including the quiet zone.
+    pub fn to_svg(&self) -> String {
+        let dimension = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.module(x, y) {
+                    let _ = write!(path, "M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE);
+                }
+            }
+        }
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {dimension} {dimension}" width="200" height="200" shape-rendering="crispEdges"><rect width="100%" height="100%" fill="#fff"/><path d="{path}" fill="#000"/></svg>"##
+        )
+    }
+
+    fn set_function_module(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        // Timing patterns
+        for i in 0..self.size {
+            self.set_function_module(6, i, i % 2 == 0);
+            self.set_function_module(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns in three corners
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size - 4, 3);
+        self.draw_finder_pattern(3, self.size - 4);
+
+        // Alignment patterns, except where they'd overlap the finder patterns
+        let positions = alignment_pattern_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                if (i, j) != (0, 0) && (i, j) != (0, last) && (i, j) != (last, 0) {
+                    self.draw_alignment_pattern(x, y);
+                }
+            }
+        }
+
+        // Reserve the format bits, they're drawn once the mask is known
+        self.draw_format_bits(0);
+        self.draw_version(version);
+    }
+
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in -4..=4_isize {
+            for dx in -4..=4_isize {
+                let (xx, yy) = (x as isize + dx, y as isize + dy);
+                if (0..self.size as isize).contains(&xx) && (0..self.size as isize).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function_module(
+                        xx as usize,
+                        yy as usize,
+                        distance != 2 && distance != 4,
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: usize, y: usize) {
+        for dy in -2..=2_isize {
+            for dx in -2..=2_isize {
+                let (xx, yy) = ((x as isize + dx) as usize, (y as isize + dy) as usize);
+                self.set_function_module(xx, yy, dx.abs().max(dy.abs()) != 1);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        // First copy, around the top left finder pattern
+        for i in 0..=5 {
+            self.set_function_module(8, i, bit(i));
+        }
+        self.set_function_module(8, 7, bit(6));
+        self.set_function_module(8, 8, bit(7));
+        self.set_function_module(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function_module(14 - i, 8, bit(i));
+        }
+
+        // Second copy, split between the other two finder patterns
+        let size = self.size;
+        for i in 0..8 {
+            self.set_function_module(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function_module(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set_function_module(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+        let bits = version_bits(version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function_module(a, b, dark);
+            self.set_function_module(b, a, dark);
+        }
+    }
+
+    /// Place the codewords in the zig-zag pattern of two module wide columns, starting at the
+    /// bottom right and skipping the function modules.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let mut i = 0;
+        let mut right = self.size - 1;
+        loop {
+            // The vertical timing pattern is skipped over
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..self.size {
+                for x in [right, right - 1] {
+                    let y = if upward { self.size - 1 - vert } else { vert };
+                    if !self.is_function[y * self.size + x] && i < codewords.len() * 8 {
+                        self.modules[y * self.size + x] =
+                            (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if invert && !self.is_function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Score the code against the rules for avoiding patterns that are hard to scan: runs of
+    /// the same colour, 2×2 blocks, things that look like finder patterns, and imbalance
+    /// between dark and light.
+    fn penalty_score(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+
+        let rows = (0..size).map(|y| (0..size).map(|x| self.module(x, y)).collect::<Vec<_>>());
+        let columns = (0..size).map(|x| (0..size).map(|y| self.module(x, y)).collect::<Vec<_>>());
+        for line in rows.chain(columns) {
+            // Runs of five or more
+            let mut run = 1;
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+                run = 1;
+            }
+
+            // 1:1:3:1:1 patterns with four light modules on either side, beyond the edge counts
+            // as light.
+            let mut padded = vec![false; QUIET_ZONE];
+            padded.extend_from_slice(&line);
+            padded.extend_from_slice(&[false; QUIET_ZONE]);
+            const FINDER: [bool; 7] = [true, false, true, true, true, false, true];
+            for window in padded.windows(11) {
+                let light = [false; 4];
+                if (window[..7] == FINDER && window[7..] == light)
+                    || (window[..4] == light && window[4..] == FINDER)
+                {
+                    penalty += 40;
+                }
+            }
+        }
+
+        // 2×2 blocks of the same colour
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.module(x, y);
+                if dark == self.module(x + 1, y)
+                    && dark == self.module(x, y + 1)
+                    && dark == self.module(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Balance of dark and light, in steps of 5% from 50%
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let k = (dark * 20).abs_diff(total * 10).div_ceil(total) - 1;
+        penalty + k * 10
+    }
+}
+
+/// Render `data` as an SVG QR Code, or `None` if it's too long to encode.
+pub fn svg(data: &str) -> Option<String> {
+    QrCode::encode(data.as_bytes()).map(|qr| qr.to_svg())
+}
+
+/// Bits packed most significant first
+#[derive(Default)]
+struct BitBuffer(Vec<bool>);
+
+impl BitBuffer {
+    fn append(&mut self, value: u32, len: usize) {
+        self.0.extend((0..len).rev().map(|i| (value >> i) & 1 != 0));
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, &bit)| byte | (u8::from(bit) << (7 - i)))
+            })
+            .collect()
+    }
+}
+
+/// Bits used for the character count in byte mode
+fn char_count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// The number of modules available for data and error correction in `version`.
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize) -> usize {
+    num_raw_data_modules(version) / 8
+        - usize::from(ECC_CODEWORDS_PER_BLOCK[version])
+            * usize::from(NUM_ERROR_CORRECTION_BLOCKS[version])
+}
+
+/// The centre coordinates of the alignment patterns, used for both rows and columns.
+fn alignment_pattern_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let num_align = version / 7 + 2;
+    let step = (version * 8 + num_align * 3 + 5) / (num_align * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions = (0..num_align - 1)
+        .map(|i| size - 7 - i * step)
+        .collect::<Vec<_>>();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// The 15 bit format information: error correction level and mask, protected by a BCH code.
+fn format_bits(mask: u32) -> u32 {
+    let data = ECC_LEVEL_M << 3 | mask;
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    (data << 10 | rem) ^ 0x5412
+}
+
+/// The 18 bit version information, protected by a BCH code.
+fn version_bits(version: usize) -> u32 {
+    let version = version as u32;
+    let mut rem = version;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+    }
+    version << 12 | rem
+}
+
+/// Split the data into blocks, append the error correction codewords to each, and interleave
+/// them.
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let num_blocks = usize::from(NUM_ERROR_CORRECTION_BLOCKS[version]);
+    let block_ecc_len = usize::from(ECC_CODEWORDS_PER_BLOCK[version]);
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = reed_solomon_divisor(block_ecc_len);
+    let mut blocks = Vec::with_capacity(num_blocks);
+    let mut rest = data;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - block_ecc_len + usize::from(i >= num_short_blocks);
+        let (block_data, remaining) = rest.split_at(data_len);
+        rest = remaining;
+        let mut block = block_data.to_vec();
+        let ecc = reed_solomon_remainder(block_data, &divisor);
+        // Short blocks are padded so that all blocks are the same length when interleaving
+        if i < num_short_blocks {
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_block_len {
+        for (j, block) in blocks.iter().enumerate() {
+            // Skip the padding of short blocks
+            if i != short_block_len - block_ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+/// The Reed-Solomon generator polynomial of `degree`, highest power first, excluding the
+/// leading term.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+/// The error correction codewords for `data`.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, &y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_multiply(y, factor);
+        }
+    }
+    result
+}
+
+/// Multiply in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((u32::from(y) >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reed_solomon() {
+        // Version 1-M "HELLO WORLD" from https://www.thonky.com/qr-code-tutorial/error-correction-coding
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        let ecc = reed_solomon_remainder(&data, &reed_solomon_divisor(10));
+        assert_eq!(ecc, [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn test_format_and_version_bits() {
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(5), 0b100000011001110);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+    }
+
+    #[test]
+    fn test_capacity() {
+        // Byte mode capacities at level M
+        assert_eq!(num_data_codewords(1), 16);
+        assert_eq!(num_data_codewords(10), 216);
+        assert_eq!(num_data_codewords(40), 2334);
+        assert_eq!(alignment_pattern_positions(32), [6, 34, 60, 86, 112, 138]);
+
+        assert_eq!(QrCode::encode(&[b'a'; 14]).unwrap().size, 21);
+        assert_eq!(QrCode::encode(&[b'a'; 15]).unwrap().size, 25);
+        assert_eq!(QrCode::encode(&[b'a'; 2331]).unwrap().size, 177);
+        assert!(QrCode::encode(&[b'a'; 2332]).is_none());
+    }
+
+    #[test]
+    fn test_finder_patterns() {
+        let qr = QrCode::encode(b"https://example.com/feed/FEEDLYNX_FEED_TOKEN").unwrap();
+        let size = qr.size;
+        for (x, y) in [(0, 0), (size - 7, 0), (0, size - 7)] {
+            let row = (0..7)
+                .map(|dx| qr.module(x + dx, y + 3))
+                .collect::<Vec<_>>();
+            assert_eq!(row, [true, false, true, true, true, false, true]);
+        }
+        // Dark module
+        assert!(qr.module(8, size - 8));
+    }
+
+    #[test]
+    fn test_svg() {
+        let svg = svg("https://example.com/").unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(r#"viewBox="0 0 33 33""#));
+        assert!(svg.contains("M4,4h1v1h-1z"));
+    }
+}
@@ -1,62 +1,226 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::fs::File;
-use std::io;
-use std::net::ToSocketAddrs;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek};
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
-use std::sync::{OnceLock, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use chrono::{DateTime, FixedOffset, Utc};
 use httpdate::fmt_http_date;
 use log::{debug, error, info, log_enabled, warn};
 use mime::Mime;
-use tiny_http::{Header, HeaderField, Method, Request, Response, StatusCode};
+use tiny_http::{Header, HeaderField, Method, Request, Response, ResponseBox, StatusCode};
 use tinyjson::JsonValue;
-use uriparse::URI;
+use uriparse::{Scheme, URI};
 
-use crate::feed::{self, Feed};
-use crate::webpage::WebPage;
-use crate::{embed, webpage, FeedToken, PrivateToken};
+use crate::feed::{self, AddResult, EntryOptions, EntryOrder, Feed, SaveOptions, TrimOptions};
+use crate::webpage::{FetchOptions, WebPage};
+use crate::{embed, gzip, multipart, qrcode, webpage, FeedToken, PrivateToken};
 
 // HTTP status codes
 const CREATED: u16 = 201;
+const ACCEPTED: u16 = 202;
+const NO_CONTENT: u16 = 204;
 const NOT_MODIFIED: u16 = 304;
 const BAD_REQUEST: u16 = 400;
 const UNAUTHORIZED: u16 = 401;
+const FORBIDDEN: u16 = 403;
 const NOT_FOUND: u16 = 404;
+const METHOD_NOT_ALLOWED: u16 = 405;
+const REQUEST_TIMEOUT: u16 = 408;
+const CONFLICT: u16 = 409;
 const PAYLOAD_TOO_LARGE: u16 = 413;
 const UNSUPPORTED_MEDIA_TYPE: u16 = 415;
 const INTERNAL_SERVER_ERROR: u16 = 500;
+const SERVICE_UNAVAILABLE: u16 = 503;
+
+/// The number of seconds clients are asked to wait before retrying when the feed file is
+/// briefly unavailable
+const FEED_RETRY_AFTER: u64 = 1;
+
+/// The name of the feed at FEED_PATH, served at `/feed/{token}`
+pub const DEFAULT_FEED: &str = "default";
 
 /// The maximum size in bytes that the server will accept in a POST to /add
 const MAX_POST_BODY: usize = 1_048_576; // 1MiB
 
+/// The maximum number of entries returned in `recent` by /info
+const MAX_RECENT: usize = 100;
+
+/// The number of threads handling requests for each listening address
+const HANDLER_THREADS: usize = 4;
+
+/// The maximum number of request bodies read at once.
+///
+/// A read that times out keeps its thread until the client sends more or closes the connection,
+/// so this limits the threads stalled clients can hold. Further POSTs are rejected until one ends.
+const MAX_BODY_READERS: usize = 16;
+
+/// The default time allowed for reading the body of a request
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default time caches and readers may reuse a feed response without checking for changes
+pub const DEFAULT_CACHE_MAX_AGE: Duration = Duration::from_secs(300);
+
 // Pre-parsed headers for reading
+static ACCEPT: OnceLock<HeaderField> = OnceLock::new();
+static ACCEPT_ENCODING: OnceLock<HeaderField> = OnceLock::new();
 static CONTENT_TYPE: OnceLock<HeaderField> = OnceLock::new();
 static HOST: OnceLock<HeaderField> = OnceLock::new();
 static IF_MODIFIED_SINCE: OnceLock<HeaderField> = OnceLock::new();
+static IF_NONE_MATCH: OnceLock<HeaderField> = OnceLock::new();
 static LAST_MODIFIED: OnceLock<HeaderField> = OnceLock::new();
+static ORIGIN: OnceLock<HeaderField> = OnceLock::new();
+static REFERER: OnceLock<HeaderField> = OnceLock::new();
 static USER_AGENT: OnceLock<HeaderField> = OnceLock::new();
+static X_FORWARDED_HOST: OnceLock<HeaderField> = OnceLock::new();
+static X_FORWARDED_PROTO: OnceLock<HeaderField> = OnceLock::new();
 
 // Pre-parsed headers for writing
+static ACCESS_CONTROL_ALLOW_HEADERS: OnceLock<Header> = OnceLock::new();
+static ACCESS_CONTROL_ALLOW_METHODS: OnceLock<Header> = OnceLock::new();
 static ACCESS_CONTROL_ORIGIN_STAR: OnceLock<Header> = OnceLock::new();
 static ATOM_CONTENT_TYPE: OnceLock<Header> = OnceLock::new();
+static CONTENT_ENCODING_GZIP: OnceLock<Header> = OnceLock::new();
+static VARY_ACCEPT_ENCODING: OnceLock<Header> = OnceLock::new();
+static VARY_ORIGIN: OnceLock<Header> = OnceLock::new();
+static X_FEEDLYNX_VERSION: OnceLock<Header> = OnceLock::new();
 static HTML_CONTENT_TYPE: OnceLock<Header> = OnceLock::new();
 static JSON_CONTENT_TYPE: OnceLock<Header> = OnceLock::new();
+static METRICS_CONTENT_TYPE: OnceLock<Header> = OnceLock::new();
+static TEXT_CONTENT_TYPE: OnceLock<Header> = OnceLock::new();
 
 pub struct Server {
-    server: tiny_http::Server,
+    /// One server per listening address
+    servers: Vec<tiny_http::Server>,
+    /// Requests are received over HTTPS rather than from a proxy terminating TLS
+    https: bool,
     private_token: PrivateToken,
     feed_path: RwLock<PathBuf>,
+    /// Additional named feeds, served at `/feed/{token}/{name}`
+    feeds: BTreeMap<String, RwLock<PathBuf>>,
     feed_route: String,
+    public_url: Option<String>,
+    trust_proxy: bool,
+    /// Origins allowed to make cross-origin requests, any origin is allowed when empty
+    cors_origins: Vec<String>,
+    /// Domains that links may be added from, any domain is allowed when empty
+    allowed_domains: Vec<String>,
+    /// Domains that links may not be added from, takes precedence over `allowed_domains`
+    blocked_domains: Vec<String>,
+    fetch_options: FetchOptions,
+    save_options: SaveOptions,
+    trim_options: TrimOptions,
+    /// The maximum number of characters of a page description stored in the feed
+    max_summary: usize,
+    entry_options: EntryOptions,
+    /// Log each request at info level in Combined Log Format
+    access_log: bool,
+    /// The time allowed for reading the body of a request
+    read_timeout: Duration,
+    /// The number of threads reading request bodies, at most [MAX_BODY_READERS]
+    body_readers: Arc<AtomicUsize>,
+    /// The `max-age` of feed responses, zero to not send `Cache-Control`
+    cache_max_age: Duration,
+    /// Prefix of all routes, E.g. `/feedlynx`, or empty when served from the root
+    base_path: String,
+    /// Queue of pages for the fetch worker when fetching asynchronously
+    fetch_queue: Option<(Sender<FetchMessage>, Mutex<Receiver<FetchMessage>>)>,
+    /// Serve `/metrics` without requiring the private token
+    public_metrics: bool,
+    entry_order: EntryOrder,
+    metrics: Metrics,
+}
+
+/// Builds a [Server] from options set in code rather than the environment.
+///
+/// Options without a builder method can be set on the built server with its `with_*` methods.
+pub struct ServerBuilder {
+    addrs: Vec<(String, u16)>,
+    private_token: Option<PrivateToken>,
+    feed_token: Option<FeedToken>,
+    feed_path: PathBuf,
+    trim_options: TrimOptions,
+    fetch_options: FetchOptions,
+    cors_origins: Vec<String>,
+    #[cfg(feature = "tls")]
+    tls: Option<tiny_http::SslConfig>,
+}
+
+/// A server handling requests on a background thread, created by [Server::spawn].
+///
+/// The server is shut down when this is dropped.
+pub struct RunningServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Counters reported by `/metrics`
+#[derive(Default)]
+struct Metrics {
+    /// Links added to a feed
+    adds: AtomicU64,
+    /// Requests to add a link that failed, including duplicates
+    add_errors: AtomicU64,
+    /// Pages that could not be fetched when adding a link
+    fetch_failures: AtomicU64,
 }
 
 struct StatusError(StatusCode, &'static str);
 
+/// The fields submitted in the body of a request
+type Fields = Vec<(Cow<'static, str>, Cow<'static, str>)>;
+
+/// The format of the body of a request, from its Content-Type
+enum BodyFormat {
+    /// `application/x-www-form-urlencoded`
+    Form,
+    /// `multipart/form-data` with the boundary between parts
+    Multipart(String),
+    /// `application/json`, an object of fields
+    Json,
+}
+
+/// A link successfully added to the feed
+struct Added {
+    /// The id of the new entry
+    id: String,
+    /// The URL stored in the entry
+    url: String,
+    /// The page is yet to be fetched by the fetch worker
+    pending: bool,
+    /// Why fetching the page for metadata failed, if it did
+    fetch_error: Option<String>,
+    /// The number of entries in the feed after adding the link
+    entry_count: usize,
+}
+
+/// A page to be fetched by the fetch worker and the entry to update with its metadata
+struct FetchJob {
+    feed_name: Option<String>,
+    id: String,
+    url: URI<'static>,
+    title: Option<String>,
+    title_override: bool,
+}
+
+enum FetchMessage {
+    Fetch(Box<FetchJob>),
+    Stop,
+}
+
 impl Server {
+    /// Create a new server listening on each of `addrs`.
+    ///
+    /// Each address is bound separately, so a dual-stack host can listen on `::` and `0.0.0.0`.
     pub fn new<A>(
-        addr: A,
+        addrs: &[A],
         private_token: PrivateToken,
         feed_token: FeedToken,
         feed_path: PathBuf,
@@ -64,181 +228,726 @@ impl Server {
     where
         A: ToSocketAddrs,
     {
-        tiny_http::Server::http(addr).map(|server| Server {
-            server,
+        let servers = bind(addrs, |addr| tiny_http::Server::http(addr))?;
+        Ok(Server::from_servers(
+            servers,
+            private_token,
+            feed_token,
+            feed_path,
+        ))
+    }
+
+    /// Create a new server serving HTTPS on each of `addrs` with the certificate and key in
+    /// `tls`.
+    #[cfg(feature = "tls")]
+    pub fn new_https<A>(
+        addrs: &[A],
+        tls: tiny_http::SslConfig,
+        private_token: PrivateToken,
+        feed_token: FeedToken,
+        feed_path: PathBuf,
+    ) -> Result<Server, Box<dyn Error + Send + Sync + 'static>>
+    where
+        A: ToSocketAddrs,
+    {
+        let servers = bind(addrs, |addr| tiny_http::Server::https(addr, tls.clone()))?;
+        let mut server = Server::from_servers(servers, private_token, feed_token, feed_path);
+        server.https = true;
+        Ok(server)
+    }
+
+    fn from_servers(
+        servers: Vec<tiny_http::Server>,
+        private_token: PrivateToken,
+        feed_token: FeedToken,
+        feed_path: PathBuf,
+    ) -> Server {
+        Server {
+            servers,
+            https: false,
             private_token,
             feed_path: RwLock::new(feed_path),
+            feeds: BTreeMap::new(),
             feed_route: format!("/feed/{}", feed_token.0),
+            public_url: None,
+            trust_proxy: false,
+            cors_origins: Vec::new(),
+            allowed_domains: Vec::new(),
+            blocked_domains: Vec::new(),
+            fetch_options: FetchOptions::default(),
+            save_options: SaveOptions::default(),
+            trim_options: TrimOptions::default(),
+            max_summary: webpage::DEFAULT_MAX_SUMMARY,
+            entry_options: EntryOptions::default(),
+            access_log: false,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            body_readers: Arc::new(AtomicUsize::new(0)),
+            cache_max_age: DEFAULT_CACHE_MAX_AGE,
+            base_path: String::new(),
+            fetch_queue: None,
+            public_metrics: false,
+            entry_order: EntryOrder::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Handle requests on a background thread until the returned server is shut down.
+    ///
+    /// Listen on port 0 to have the system choose a free port, then use
+    /// [server_addrs](Server::server_addrs) to find out which one.
+    pub fn spawn(self) -> io::Result<RunningServer> {
+        let server = Arc::new(self);
+        let server2 = Arc::clone(&server);
+        let thread = thread::Builder::new()
+            .name("server".to_string())
+            .spawn(move || server2.handle_requests())?;
+        Ok(RunningServer {
+            server,
+            thread: Some(thread),
         })
     }
 
+    /// Determine if the server serves HTTPS itself.
+    pub fn is_https(&self) -> bool {
+        self.https
+    }
+
+    /// The addresses the server is listening on
+    pub fn server_addrs(&self) -> Vec<SocketAddr> {
+        self.servers
+            .iter()
+            .filter_map(|server| server.server_addr().to_ip())
+            .collect()
+    }
+
+    /// Set the URL the server is publicly reachable at, E.g. `https://feedlynx.example.com`.
+    ///
+    /// This is used when displaying URLs on the index page. When not set the URL is derived
+    /// from the request.
+    pub fn with_public_url(mut self, public_url: Option<String>) -> Self {
+        self.public_url = public_url.map(|url| url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Trust the X-Forwarded-Host and X-Forwarded-Proto headers set by a reverse proxy.
+    ///
+    /// When enabled these headers are used in place of the Host header when deriving the URL
+    /// of the server. They can be set by any client so this should only be enabled when the
+    /// server is only reachable via a proxy that sets them.
+    pub fn with_trust_proxy(mut self, trust_proxy: bool) -> Self {
+        self.trust_proxy = trust_proxy;
+        self
+    }
+
+    /// Set the origins that are allowed to make cross-origin requests to `/add` and `/info`.
+    ///
+    /// When empty any origin is allowed (`Access-Control-Allow-Origin: *`). Otherwise the Origin
+    /// of the request is echoed back only if it's in the list.
+    pub fn with_cors_origins(mut self, cors_origins: Vec<String>) -> Self {
+        self.cors_origins = cors_origins;
+        self
+    }
+
+    /// Set the domains that links may be added from.
+    ///
+    /// When empty links from any domain can be added. Otherwise the host of the link must be
+    /// one of the domains or a subdomain of one, E.g. `example.com` allows `www.example.com`.
+    pub fn with_allowed_domains(mut self, allowed_domains: Vec<String>) -> Self {
+        self.allowed_domains = allowed_domains;
+        self
+    }
+
+    /// Set the domains that links may not be added from.
+    ///
+    /// Subdomains are matched like [with_allowed_domains](Server::with_allowed_domains). A
+    /// domain that is both allowed and blocked is blocked.
+    pub fn with_blocked_domains(mut self, blocked_domains: Vec<String>) -> Self {
+        self.blocked_domains = blocked_domains;
+        self
+    }
+
+    /// Set the options used when fetching added pages for metadata.
+    pub fn with_fetch_options(mut self, fetch_options: FetchOptions) -> Self {
+        self.fetch_options = fetch_options;
+        self
+    }
+
+    /// Set the options used when saving the feed.
+    pub fn with_save_options(mut self, save_options: SaveOptions) -> Self {
+        self.save_options = save_options;
+        self
+    }
+
+    /// Set the maximum number of characters of a page description stored in the feed.
+    ///
+    /// Longer descriptions are truncated. `0` means no limit.
+    pub fn with_max_summary(mut self, max_summary: usize) -> Self {
+        self.max_summary = max_summary;
+        self
+    }
+
+    /// Set the options used to build the entries of links added to the feed.
+    pub fn with_entry_options(mut self, entry_options: EntryOptions) -> Self {
+        self.entry_options = entry_options;
+        self
+    }
+
+    /// Log each request at info level in Combined Log Format.
+    ///
+    /// When disabled requests are only logged at debug level.
+    pub fn with_access_log(mut self, access_log: bool) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    /// Set the time allowed for reading the body of a request.
+    ///
+    /// Requests that take longer are rejected with `408 Request Timeout`. A client that stops
+    /// sending data entirely is given up on after the timeout, freeing the thread handling it,
+    /// but is only sent the response once it sends more or closes the connection.
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Set the `max-age` of the `Cache-Control` header sent with feed responses.
+    ///
+    /// A zero duration disables the header.
+    pub fn with_cache_max_age(mut self, cache_max_age: Duration) -> Self {
+        self.cache_max_age = cache_max_age;
+        self
+    }
+
+    /// Serve all routes under `base_path`, E.g. `/feedlynx`, for hosting on a subpath behind
+    /// a reverse proxy.
+    ///
+    /// The prefix is stripped before matching routes so `/feedlynx/add` is handled as `/add`.
+    pub fn with_base_path(mut self, base_path: Option<String>) -> Self {
+        self.base_path = base_path
+            .as_deref()
+            .map(|path| path.trim_matches('/'))
+            .filter(|path| !path.is_empty())
+            .map(|path| format!("/{path}"))
+            .unwrap_or_default();
+        self
+    }
+
+    /// Fetch added pages in the background.
+    ///
+    /// When enabled links are added with the supplied title straight away, and the response is
+    /// sent without waiting for the page to be fetched. A worker thread then fetches the page and
+    /// updates the entry with its metadata.
+    pub fn with_async_fetch(mut self, async_fetch: bool) -> Self {
+        self.fetch_queue = async_fetch.then(|| {
+            let (sender, receiver) = mpsc::channel();
+            (sender, Mutex::new(receiver))
+        });
+        self
+    }
+
+    /// Serve `/metrics` to anyone.
+    ///
+    /// When disabled the private token must be supplied in the `token` query parameter.
+    pub fn with_public_metrics(mut self, public_metrics: bool) -> Self {
+        self.public_metrics = public_metrics;
+        self
+    }
+
+    /// Set the order entries are listed in when feeds are served.
+    ///
+    /// Entries are stored oldest first, so feeds listed newest first are read and reordered for
+    /// each request rather than being sent straight from the file.
+    pub fn with_entry_order(mut self, entry_order: EntryOrder) -> Self {
+        self.entry_order = entry_order;
+        self
+    }
+
+    /// Set the additional named feeds and their paths.
+    ///
+    /// Each feed is served at `/feed/{token}/{name}` and links are added to it by including
+    /// its name in the `feed` field when adding.
+    pub fn with_feeds(mut self, feeds: Vec<(String, PathBuf)>) -> Self {
+        self.feeds = feeds
+            .into_iter()
+            .map(|(name, path)| (name, RwLock::new(path)))
+            .collect();
+        self
+    }
+
     pub fn handle_requests(&self) {
         // initialize statics
+        let _ = ACCEPT.set("Accept".parse().unwrap());
+        let _ = ACCEPT_ENCODING.set("Accept-Encoding".parse().unwrap());
         let _ = CONTENT_TYPE.set("Content-Type".parse().unwrap());
         let _ = HOST.set("Host".parse().unwrap());
         let _ = IF_MODIFIED_SINCE.set("If-Modified-Since".parse().unwrap());
+        let _ = IF_NONE_MATCH.set("If-None-Match".parse().unwrap());
         let _ = LAST_MODIFIED.set("Last-Modified".parse().unwrap());
+        let _ = ORIGIN.set("Origin".parse().unwrap());
+        let _ = REFERER.set("Referer".parse().unwrap());
         let _ = USER_AGENT.set("User-Agent".parse().unwrap());
+        let _ = X_FORWARDED_HOST.set("X-Forwarded-Host".parse().unwrap());
+        let _ = X_FORWARDED_PROTO.set("X-Forwarded-Proto".parse().unwrap());
 
+        let _ = ACCESS_CONTROL_ALLOW_HEADERS.set(
+            "Access-Control-Allow-Headers: Content-Type"
+                .parse()
+                .unwrap(),
+        );
+        let _ =
+            ACCESS_CONTROL_ALLOW_METHODS.set("Access-Control-Allow-Methods: POST".parse().unwrap());
         let _ = ACCESS_CONTROL_ORIGIN_STAR.set("Access-Control-Allow-Origin: *".parse().unwrap());
-        let _ = ATOM_CONTENT_TYPE.set("Content-type: application/atom+xml".parse().unwrap());
+        let _ = CONTENT_ENCODING_GZIP.set("Content-Encoding: gzip".parse().unwrap());
+        let _ = VARY_ACCEPT_ENCODING.set("Vary: Accept-Encoding".parse().unwrap());
+        let _ = VARY_ORIGIN.set("Vary: Origin".parse().unwrap());
+        let _ = X_FEEDLYNX_VERSION
+            .set(Header::from_bytes("X-Feedlynx-Version", env!("CARGO_PKG_VERSION")).unwrap());
+        let _ = ATOM_CONTENT_TYPE.set(
+            "Content-type: application/atom+xml; charset=utf-8"
+                .parse()
+                .unwrap(),
+        );
         let _ = HTML_CONTENT_TYPE.set("Content-type: text/html; charset=utf-8".parse().unwrap());
         let _ = JSON_CONTENT_TYPE.set("Content-type: application/json".parse().unwrap());
+        let _ = METRICS_CONTENT_TYPE.set(
+            "Content-type: text/plain; version=0.0.4; charset=utf-8"
+                .parse()
+                .unwrap(),
+        );
+        let _ = TEXT_CONTENT_TYPE.set("Content-type: text/plain; charset=utf-8".parse().unwrap());
 
         info!(
             "Feed trimming policy: Min entries: {}, trim age: {} days",
-            feed::MIN_ENTRIES,
-            feed::TRIM_AGE.num_days()
+            self.trim_options.min_entries,
+            self.trim_options.max_age.num_days()
         );
-        info!(
-            "Feed available at: http://{}{}",
-            self.server.server_addr(),
-            self.feed_route
+        let scheme = if self.https { "https" } else { "http" };
+        let base_url = format!(
+            "{scheme}://{}{}",
+            self.servers[0].server_addr(),
+            self.base_path
         );
+        info!("Feed available at: {}", self.feed_url(&base_url, None));
+        for name in self.feeds.keys() {
+            info!(
+                "Feed '{name}' available at: {}",
+                self.feed_url(&base_url, Some(name))
+            );
+        }
 
-        for mut request in self.server.incoming_requests() {
-            let response = match (request.method(), request.url()) {
-                (Method::Get, "/") => {
-                    let body = self.index(&request);
-                    Response::from_string(body)
-                        .with_header(HTML_CONTENT_TYPE.get().cloned().unwrap())
+        // Handle requests for each address on several threads so that a slow request, such as
+        // one fetching a page, doesn't hold up the others
+        thread::scope(|scope| {
+            if let Some((_, receiver)) = &self.fetch_queue {
+                scope.spawn(move || self.fetch_worker(receiver));
+            }
+            for server in &self.servers {
+                for _ in 0..HANDLER_THREADS {
+                    scope.spawn(move || {
+                        for request in server.incoming_requests() {
+                            self.handle_request(request);
+                        }
+                    });
                 }
-                // TODO: Handle query args (I.e. ignore them?)
-                // This branch has a different response type so we have to call respond and continue
-                // instead of falling through to the code at the bottom.
-                (Method::Get, path) if path == self.feed_route => {
-                    let feed_path = self.feed_path.read().expect("poisoned");
-                    match File::open(&*feed_path) {
-                        Ok(file) => {
-                            let modified = file.metadata().and_then(|meta| meta.modified()).ok();
-                            let if_modified_since = request
-                                .headers()
-                                .iter()
-                                .find(|&header| &header.field == IF_MODIFIED_SINCE.get().unwrap())
-                                .and_then(|header| {
-                                    httpdate::parse_http_date(header.value.as_str()).ok()
-                                });
-
-                            match (modified, if_modified_since) {
-                                // Send 304 response
-                                (Some(modified), Some(ifs)) if not_modified(modified, ifs) => {
-                                    // https://www.rfc-editor.org/rfc/rfc7232#page-18 suggests Last-Modified should
-                                    // still be included in the 304 response
-                                    let response =
-                                        Response::empty(NOT_MODIFIED).with_header(Header {
-                                            field: LAST_MODIFIED.get().cloned().unwrap(),
-                                            // NOTE(unwrap): we always expect ASCII from fmt_http_date
-                                            value: fmt_http_date(modified).parse().unwrap(),
-                                        });
-                                    self.log_request(&request, response.status_code());
-                                    match request.respond(response) {
-                                        Ok(()) => {}
-                                        Err(err) => error!("Failed to send response: {err}"),
-                                    }
-                                    continue;
-                                }
-                                _ => {}
-                            }
+            }
+        });
+    }
+
+    fn handle_request(&self, request: Request) {
+        let (request, body) = if request.method() == &Method::Post {
+            let Some(reader) = BodyReader::acquire(&self.body_readers) else {
+                error!("Too many POST bodies being read");
+                let response = Response::from_string("Failed: Too many requests being received\n")
+                    .with_status_code(SERVICE_UNAVAILABLE)
+                    .with_header(header(&TEXT_CONTENT_TYPE));
+                self.respond(request, response, false);
+                return;
+            };
+            match receive_body(request, self.read_timeout, reader) {
+                Some(received) => received,
+                None => return,
+            }
+        } else {
+            (request, Ok(Vec::new()))
+        };
+
+        // Route on the path alone, query parameters are handled by the individual handlers.
+        // Paths outside the base path don't match any route. Some proxies percent-encode
+        // characters in the path, so it's decoded before matching.
+        let path = percent_decode(request.url().split('?').next().unwrap_or_default());
+        let path = self.strip_base_path(&path).unwrap_or_default();
+        let cors = matches!(path, "/add" | "/info" | "/mark");
+        let requested_feed = self.route_feed(path);
+        let response = match (request.method(), path) {
+            (Method::Get, "/") => {
+                let body = self.index(&request);
+                Response::from_string(body).with_header(header(&HTML_CONTENT_TYPE))
+            }
+            // This branch has a different response type so we have to call respond and return
+            // instead of falling through to the code at the bottom.
+            (Method::Get | Method::Head, _) if requested_feed.is_some() => {
+                // NOTE(unwrap): checked by the guard
+                let feed_lock = requested_feed.unwrap();
+                // The lock is only held while opening the feed. The open file is unaffected by
+                // the feed being replaced while it's sent.
+                let (feed_path, file) = {
+                    let feed_path = feed_lock.read().expect("poisoned");
+                    (feed_path.clone(), File::open(&*feed_path))
+                };
+                match file {
+                    Ok(file) => {
+                        // The validators come from fstat on the file being served, so they
+                        // reflect changes made outside the server and match the content sent,
+                        // even if the feed is replaced after it was opened.
+                        let metadata = file.metadata().ok();
+                        let modified = metadata.as_ref().and_then(|meta| meta.modified().ok());
+                        let etag = metadata.as_ref().and_then(etag);
+                        let if_modified_since = header_value(&request, &IF_MODIFIED_SINCE)
+                            .and_then(|value| httpdate::parse_http_date(value).ok());
+                        let if_none_match = header_value(&request, &IF_NONE_MATCH);
+
+                        // If-None-Match takes precedence over If-Modified-Since
+                        // https://www.rfc-editor.org/rfc/rfc7232#section-6
+                        let unchanged = match (&etag, if_none_match, modified, if_modified_since) {
+                            (Some(etag), Some(inm), _, _) => etag_matches(etag, inm),
+                            (_, None, Some(modified), Some(ifs)) => not_modified(modified, ifs),
+                            _ => false,
+                        };
+
+                        let mut cache_headers = Vec::new();
+                        if let Some(modified) = modified {
+                            cache_headers.push(Header {
+                                field: LAST_MODIFIED.get().cloned().unwrap(),
+                                // NOTE(unwrap): we always expect ASCII from fmt_http_date
+                                value: fmt_http_date(modified).parse().unwrap(),
+                            });
+                        }
+                        if let Some(etag) = &etag {
+                            // NOTE(unwrap): the ETag is always ASCII
+                            cache_headers
+                                .push(Header::from_bytes("ETag", etag.as_bytes()).unwrap());
+                        }
+                        if !self.cache_max_age.is_zero() {
+                            let value = format!("max-age={}", self.cache_max_age.as_secs());
+                            // NOTE(unwrap): the value is always ASCII
+                            cache_headers.push(Header::from_bytes("Cache-Control", value).unwrap());
+                        }
+
+                        // Send 304 response
+                        if unchanged {
+                            // https://www.rfc-editor.org/rfc/rfc7232#page-18 suggests Last-Modified should
+                            // still be included in the 304 response, along with the ETag
+                            let mut response = Response::empty(NOT_MODIFIED);
+                            cache_headers
+                                .into_iter()
+                                .for_each(|header| response.add_header(header));
+                            self.respond(request, response, cors);
+                            return;
+                        }
 
-                            // Send 200 response with File
-                            let mut response = Response::from_file(file)
-                                .with_header(ATOM_CONTENT_TYPE.get().cloned().unwrap());
-                            if let Some(modified) = modified {
-                                response = response.with_header(Header {
-                                    field: LAST_MODIFIED.get().cloned().unwrap(),
-                                    // NOTE(unwrap): we always expect ASCII from fmt_http_date
-                                    value: fmt_http_date(modified).parse().unwrap(),
-                                });
+                        // Send 200 response with File, or the entries with the requested tag
+                        let head = request.method() == &Method::Head;
+                        let query = query_string(&request);
+                        let param = |name: &str| {
+                            form_urlencoded::parse(query.as_bytes())
+                                .find_map(|(key, value)| (key == name).then_some(value))
+                        };
+                        let tag = param("tag").filter(|tag| !tag.is_empty());
+                        // An unparsable since is ignored and the full feed served
+                        let since = param("since").and_then(|since| parse_since(&since));
+                        let filter = FeedFilter {
+                            tag: tag.as_deref(),
+                            since,
+                            order: self.entry_order,
+                        };
+                        let response = if filter.is_nop() {
+                            feed_response(
+                                file,
+                                gzip::is_gzip_path(&feed_path),
+                                accepts_gzip(&request),
+                                head,
+                            )
+                            .map_err(crate::Error::from)
+                        } else {
+                            filtered_feed_response(
+                                feed_lock,
+                                &filter,
+                                accepts_gzip(&request),
+                                &self.save_options,
+                            )
+                        };
+                        match response {
+                            Ok(response) => {
+                                let mut response = response.with_header(header(&ATOM_CONTENT_TYPE));
+                                cache_headers
+                                    .into_iter()
+                                    .for_each(|header| response.add_header(header));
+                                self.respond(request, response, cors);
+                                return;
                             }
-                            self.log_request(&request, response.status_code());
-                            match request.respond(response) {
-                                Ok(()) => {}
-                                Err(err) => error!("Failed to send response: {err}"),
+                            Err(err) => {
+                                error!("Unable to read feed file: {}", err);
+                                Response::from_string(embed!("500.html"))
+                                    .with_header(header(&HTML_CONTENT_TYPE))
+                                    .with_status_code(INTERNAL_SERVER_ERROR)
                             }
-                            continue;
-                        }
-                        Err(err) => {
-                            error!("Unable to open feed file: {}", err);
-                            Response::from_string(embed!("500.html"))
-                                .with_status_code(INTERNAL_SERVER_ERROR)
                         }
                     }
-                }
-                (Method::Post, "/add") => match self.add(&mut request) {
-                    Ok(()) => Response::from_string("Added\n")
-                        .with_header(ACCESS_CONTROL_ORIGIN_STAR.get().cloned().unwrap())
-                        .with_status_code(CREATED),
-                    Err(StatusError(status, error)) => {
-                        Response::from_string(format!("Failed: {error}\n"))
-                            .with_header(ACCESS_CONTROL_ORIGIN_STAR.get().cloned().unwrap())
-                            .with_status_code(status)
-                    }
-                },
-                (Method::Post, "/info") => match self.info(&mut request) {
-                    Ok(info) => {
-                        let json = JsonValue::Object(info);
-                        // NOTE(unwrap): io::Error should not happen when writing to a String
-                        Response::from_string(tinyjson::stringify(&json).unwrap())
-                            .with_header(JSON_CONTENT_TYPE.get().cloned().unwrap())
-                            .with_header(ACCESS_CONTROL_ORIGIN_STAR.get().cloned().unwrap())
+                    // On some filesystems an open racing with the rename that replaces the feed
+                    // can briefly fail, so the client is asked to try again shortly.
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                        ) =>
+                    {
+                        warn!("Feed file temporarily unavailable: {}", err);
+                        // NOTE(unwrap): the value is always ASCII
+                        Response::from_string("Feed temporarily unavailable, try again shortly\n")
+                            .with_status_code(SERVICE_UNAVAILABLE)
+                            .with_header(header(&TEXT_CONTENT_TYPE))
+                            .with_header(
+                                Header::from_bytes("Retry-After", FEED_RETRY_AFTER.to_string())
+                                    .unwrap(),
+                            )
                     }
-                    Err(StatusError(status, error)) => {
-                        let map = IntoIterator::into_iter([
-                            ("status".to_string(), JsonValue::from("error".to_string())),
-                            ("message".to_string(), JsonValue::from(error.to_string())),
-                        ])
-                        .collect();
-                        let json = JsonValue::Object(map);
-                        // NOTE(unwrap): io::Error should not happen when writing to a String
-                        Response::from_string(tinyjson::stringify(&json).unwrap())
-                            .with_header(JSON_CONTENT_TYPE.get().cloned().unwrap())
-                            .with_header(ACCESS_CONTROL_ORIGIN_STAR.get().cloned().unwrap())
-                            .with_status_code(status)
+                    Err(err) => {
+                        error!("Unable to open feed file: {}", err);
+                        Response::from_string(embed!("500.html"))
+                            .with_header(header(&HTML_CONTENT_TYPE))
+                            .with_status_code(INTERNAL_SERVER_ERROR)
                     }
-                },
-                _ => Response::from_string(embed!("404.html"))
-                    .with_header(HTML_CONTENT_TYPE.get().cloned().unwrap())
+                }
+            }
+            (Method::Get, "/add") if is_submit(&request) => {
+                let query = query_string(&request);
+                let result = self.add_link(&request, form_urlencoded::parse(query.as_bytes()));
+                self.count_add(&result);
+                add_response(result, accepts_json(&request))
+            }
+            (Method::Get, "/add") => match self.add_form(&request) {
+                Ok(body) => Response::from_string(body).with_header(header(&HTML_CONTENT_TYPE)),
+                Err(StatusError(status, error)) => {
+                    Response::from_string(format!("Failed: {error}\n")).with_status_code(status)
+                }
+            },
+            (Method::Post, "/add") => {
+                let result = self.add(&request, body);
+                self.count_add(&result);
+                add_response(result, accepts_json(&request))
+            }
+            // CORS preflight, the Access-Control-Allow-Origin header is added below
+            (Method::Options, "/add" | "/info" | "/mark") => Response::from_string("")
+                .with_header(header(&ACCESS_CONTROL_ALLOW_METHODS))
+                .with_header(header(&ACCESS_CONTROL_ALLOW_HEADERS))
+                .with_status_code(NO_CONTENT),
+            (Method::Post, "/mark") => match self.mark(&request, body) {
+                Ok((id, read)) => Response::from_string(format!(
+                    "Marked {}\nid: {id}\n",
+                    if read { "read" } else { "unread" }
+                )),
+                Err(StatusError(status, error)) => {
+                    Response::from_string(format!("Failed: {error}\n")).with_status_code(status)
+                }
+            },
+            (Method::Post, "/clear") => match self.clear(&request, body) {
+                Ok(removed) => Response::from_string(format!("Cleared\nremoved: {removed}\n")),
+                Err(StatusError(status, error)) => {
+                    Response::from_string(format!("Failed: {error}\n")).with_status_code(status)
+                }
+            },
+            (Method::Post, "/info") => json_response(self.info(&request, body)),
+            (Method::Get, "/feeds") => json_response(self.feeds(&request)),
+            (Method::Get, "/metrics") => match self.metrics(&request) {
+                Ok(body) => Response::from_string(body).with_header(header(&METRICS_CONTENT_TYPE)),
+                Err(StatusError(status, error)) => {
+                    Response::from_string(format!("Failed: {error}\n")).with_status_code(status)
+                }
+            },
+            (Method::Get, "/version") => json_response(Ok(IntoIterator::into_iter([(
+                "version".to_string(),
+                JsonValue::from(env!("CARGO_PKG_VERSION").to_string()),
+            )])
+            .collect())),
+            (_, path) => match allowed_methods(path, requested_feed.is_some()) {
+                // NOTE(unwrap): the list of methods is always a valid header value
+                Some(allow) => Response::from_string("Method Not Allowed\n")
+                    .with_header(Header::from_bytes("Allow", allow).unwrap())
+                    .with_status_code(METHOD_NOT_ALLOWED),
+                None => Response::from_string(embed!("404.html"))
+                    .with_header(header(&HTML_CONTENT_TYPE))
                     .with_status_code(NOT_FOUND),
-            };
+            },
+        };
+
+        self.respond(request, response, cors);
+    }
+
+    /// Add the headers common to all responses and send `response`.
+    ///
+    /// Headers that apply to every response, or every response of a kind, like the CORS headers,
+    /// should be added here so that no branch of [Server::handle_request] misses them.
+    fn respond<R: Read>(&self, request: Request, mut response: Response<R>, cors: bool) {
+        if cors {
+            self.cors_headers(&request)
+                .into_iter()
+                .for_each(|header| response.add_header(header));
+        }
+        response.add_header(header(&X_FEEDLYNX_VERSION));
 
-            self.log_request(&request, response.status_code());
+        self.log_request(&request, response.status_code(), response.data_length());
 
-            match request.respond(response) {
-                Ok(()) => {}
-                Err(err) => error!("Failed to send response: {err}"),
-            }
+        match request.respond(response) {
+            Ok(()) => {}
+            Err(err) => error!("Failed to send response: {err}"),
         }
     }
 
     fn index(&self, request: &Request) -> String {
         let logo = embed!("../feedlynx.svg");
-        let host = request
-            .headers()
-            .iter()
-            .find_map(|header| {
-                (&header.field == HOST.get().unwrap()).then(|| Cow::from(header.value.as_str()))
-            })
-            .unwrap_or_else(|| Cow::from(self.server.server_addr().to_string()));
-        let feed_url = format!("http://{host}/feed/FEEDLYNX_FEED_TOKEN");
+        let base_url = self.base_url(request);
+        let feed_url = format!("{base_url}/feed/FEEDLYNX_FEED_TOKEN");
+        let add_url = format!("{base_url}/add");
+        let qr = qrcode::svg(&feed_url).unwrap_or_default();
         embed!("index.html")
             .into_owned()
             .replace("{{logo}}", &logo)
-            .replace("{{feed}}", &feed_url)
+            .replace("{{qr}}", &qr)
+            .replace("{{feed}}", &webpage::escape_html(&feed_url))
+            .replace("{{add_url}}", &webpage::escape_html(&add_url))
+            .replace("{{base_url}}", &webpage::escape_html(&base_url))
     }
 
-    fn add(&self, request: &mut Request) -> Result<(), StatusError> {
-        self.validate_request(request)?;
-        let body = read_body(request)?;
+    /// Render the form for adding a link, pre-filled from the query string.
+    ///
+    /// The private token must be supplied in the `token` query parameter.
+    fn add_form(&self, request: &Request) -> Result<String, StatusError> {
+        let query = query_string(request);
 
-        // Parse the form submission and extract the token and url
         let mut token = None;
         let mut url = None;
         let mut title = None;
+        form_urlencoded::parse(query.as_bytes()).for_each(|(key, value)| match &*key {
+            "token" => token = Some(value),
+            "url" => url = Some(value),
+            "title" => title = Some(value),
+            _ => {}
+        });
+
+        let token = token.ok_or_else(|| StatusError::new(UNAUTHORIZED, "Missing token"))?;
+        if self.private_token != *token {
+            return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
+        }
+
+        let logo = embed!("../feedlynx.svg");
+        Ok(embed!("add.html")
+            .into_owned()
+            .replace("{{logo}}", &logo)
+            .replace(
+                "{{add_path}}",
+                &webpage::escape_html(&format!("{}/add", self.base_path)),
+            )
+            .replace("{{token}}", &webpage::escape_html(&token))
+            .replace(
+                "{{url}}",
+                &webpage::escape_html(url.as_deref().unwrap_or_default()),
+            )
+            .replace(
+                "{{title}}",
+                &webpage::escape_html(title.as_deref().unwrap_or_default()),
+            ))
+    }
+
+    /// The URL that the server is reachable at, without a trailing slash.
+    ///
+    /// Uses the configured public URL if set, otherwise it's derived from the Host header of the
+    /// request and the base path.
+    fn base_url(&self, request: &Request) -> String {
+        if let Some(public_url) = &self.public_url {
+            return public_url.clone();
+        }
+
+        // Proxies may append to these headers so the first value is the one set by the client
+        // facing proxy.
+        let forwarded = |field| {
+            header_value(request, field)
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let (forwarded_host, forwarded_proto) = if self.trust_proxy {
+            (forwarded(&X_FORWARDED_HOST), forwarded(&X_FORWARDED_PROTO))
+        } else {
+            (None, None)
+        };
 
-        form_urlencoded::parse(&body).for_each(|(key, value)| match &*key {
+        let scheme = match forwarded_proto {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            _ if self.https => "https",
+            _ => "http",
+        };
+        let host = forwarded_host
+            .or_else(|| header_value(request, &HOST))
+            .map(Cow::from)
+            .unwrap_or_else(|| Cow::from(self.servers[0].server_addr().to_string()));
+        format!("{scheme}://{host}{}", self.base_path)
+    }
+
+    /// The CORS headers to include in the response to `request`.
+    fn cors_headers(&self, request: &Request) -> Vec<Header> {
+        if self.cors_origins.is_empty() {
+            return vec![header(&ACCESS_CONTROL_ORIGIN_STAR)];
+        }
+
+        let allow_origin = header_value(request, &ORIGIN)
+            .filter(|&origin| self.cors_origins.iter().any(|allowed| allowed == origin))
+            .and_then(|origin| Header::from_bytes("Access-Control-Allow-Origin", origin).ok());
+
+        // The response varies by origin so caches need to take it into account
+        allow_origin
+            .into_iter()
+            .chain([header(&VARY_ORIGIN)])
+            .collect()
+    }
+
+    fn add(
+        &self,
+        request: &Request,
+        body: Result<Vec<u8>, StatusError>,
+    ) -> Result<Added, StatusError> {
+        let fields = self.read_fields(request, body)?;
+        self.add_link(request, fields)
+    }
+
+    /// Add a link to the feed from the fields of a form submission or query string.
+    fn add_link<'a>(
+        &self,
+        request: &Request,
+        fields: impl IntoIterator<Item = (Cow<'a, str>, Cow<'a, str>)>,
+    ) -> Result<Added, StatusError> {
+        // Extract the token and url
+        let mut token = None;
+        let mut url = None;
+        let mut title = None;
+        let mut tags = Vec::new();
+        let mut feed_name = None;
+        let mut title_override = false;
+        let mut source = None;
+
+        fields.into_iter().for_each(|(key, value)| match &*key {
             "token" => token = Some(value),
             "url" => url = Some(value),
             "title" => title = Some(value),
+            "title_override" => title_override = value == "1" || value == "true",
+            "feed" => feed_name = Some(value).filter(|name| !name.is_empty()),
+            "source" => source = Some(value).filter(|source| !source.trim().is_empty()),
+            "tags" => tags.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from),
+            ),
             _ => {}
         });
 
@@ -250,49 +959,391 @@ impl Server {
         }
 
         // Parse URL
-        let Some(url) = url.as_ref().and_then(|u| URI::try_from(u.as_ref()).ok()) else {
+        let url = url.ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing url"))?;
+        let Ok(url) = URI::try_from(url.as_ref()) else {
             return Err(StatusError::new(BAD_REQUEST, "Invalid URL"));
         };
 
-        // Fetch the page for extra metadata
-        let mut page = match webpage::fetch(url.to_string()) {
-            Ok(page) => page,
+        // Only web pages can be fetched and linked to
+        if !matches!(url.scheme(), Scheme::HTTP | Scheme::HTTPS) {
+            return Err(StatusError::new(BAD_REQUEST, "Unsupported URL scheme"));
+        }
+
+        if !self.domain_allowed(&url) {
+            info!("Domain not allowed {}", url);
+            return Err(StatusError::new(FORBIDDEN, "Domain not allowed"));
+        }
+
+        let feed_lock = self
+            .feed_lock(feed_name.as_deref())
+            .ok_or_else(|| StatusError::new(BAD_REQUEST, "Unknown feed"))?;
+
+        // Avoid fetching the page if it's already in the feed. This is checked again when
+        // adding the link in case it was added concurrently.
+        if read_feed(feed_lock)?.contains_url(&url) {
+            info!("Duplicate {}", url);
+            return Err(StatusError::new(CONFLICT, "Duplicate link"));
+        }
+
+        // In async mode a provisional entry with the supplied title is added and the page is
+        // fetched by the fetch worker, which then updates the entry.
+        let queue = self.fetch_queue.as_ref().map(|(sender, _)| sender);
+        let (page, fetch_error) = match queue {
+            Some(_) => {
+                let page = WebPage {
+                    title: title.as_deref().map(String::from),
+                    ..Default::default()
+                };
+                (page, None)
+            }
+            None => self.fetch_page(&url, title.as_deref(), title_override),
+        };
+
+        // Add to the feed. The feed is read, modified, and saved while holding the lock so that
+        // simultaneous requests don't overwrite each others changes. Anything that can be done
+        // beforehand, like fetching the page, is to keep other requests waiting for as little
+        // time as possible.
+        let self_url = self.feed_url(&self.base_url(request), feed_name.as_deref());
+        let feed_path = feed_lock.write().expect("poisoned");
+        let mut feed = Feed::read(&*feed_path).map_err(|err| {
+            error!("Unable to read feed file: {err}");
+            StatusError::new(INTERNAL_SERVER_ERROR, "Unable to read feed file")
+        })?;
+        feed.set_entry_options(self.entry_options.clone());
+        let AddResult::Added(id) = feed.add_url_if_new(&url, page, &tags) else {
+            // Nothing changed so there's no need to save the feed
+            info!("Duplicate {}", url);
+            return Err(StatusError::new(CONFLICT, "Duplicate link"));
+        };
+        // The URL of the feed is only known from requests so it's updated each time a link is
+        // added. It includes the feed token since that's how the feed is accessed.
+        if let Some(source) = &source {
+            feed.set_source(&id, source.trim());
+        }
+        feed.set_self_link(self_url);
+        let entry_count = feed.trim_entries(&self.trim_options);
+        feed.save(&self.save_options).map_err(|err| {
+            error!("Unable to save feed: {err}");
+            StatusError::new(INTERNAL_SERVER_ERROR, "Error saving feed file")
+        })?;
+        drop(feed_path);
+
+        let added = Added {
+            id,
+            url: feed::normalize_url(&url),
+            pending: queue.is_some(),
+            fetch_error,
+            entry_count,
+        };
+        if let Some(sender) = queue {
+            let job = FetchJob {
+                feed_name: feed_name.map(Cow::into_owned),
+                id: added.id.clone(),
+                url: url.into_owned(),
+                title: title.map(Cow::into_owned),
+                title_override,
+            };
+            if sender.send(FetchMessage::Fetch(Box::new(job))).is_err() {
+                warn!(
+                    "Fetch worker is not running, {} will not be updated",
+                    added.url
+                );
+            }
+        }
+        Ok(added)
+    }
+
+    /// Fetch `url` for metadata, combining it with the `title` supplied when adding it.
+    ///
+    /// If the page can't be fetched only the supplied title is used, and the error is returned
+    /// alongside so that it can be reported to the client.
+    fn fetch_page(
+        &self,
+        url: &URI,
+        title: Option<&str>,
+        title_override: bool,
+    ) -> (WebPage, Option<String>) {
+        let (mut page, fetch_error) = match webpage::fetch(url.to_string(), &self.fetch_options) {
+            Ok(page) => (page, None),
             Err(err) => {
                 warn!("Failed to fetch {}: {err}", url);
-                WebPage::default()
+                self.metrics.fetch_failures.fetch_add(1, Ordering::Relaxed);
+                (WebPage::default(), Some(err.to_string()))
             }
         };
 
         // Use the title supplied in the request if its longer than that fetched from the page.
         // This aims to handle cases like YouTube where fetching the video URL returns a
         // Challenge page to prove you aren't a bot with a generic title and description.
-        if let Some(title) = &title {
+        // If the page was detected as a challenge page then the supplied title is always
+        // preferred, and the description, which is probably about the challenge, is dropped.
+        // With title_override the supplied title is always used as is.
+        if let (true, Some(title)) = (title_override, title) {
+            page.title = Some(title.to_string());
+        } else if page.low_confidence {
+            debug!("{} looks like a challenge page", url);
+            page.description = None;
+            if let Some(title) = title {
+                page.title = Some(title.to_string());
+            }
+        } else if let Some(title) = title {
             webpage::set_if_longer(&mut page.title, title);
         }
+        if self.max_summary > 0 {
+            page.truncate_description(self.max_summary);
+        }
+        (page, fetch_error)
+    }
+
+    /// Fetch the pages queued by [Server::add_link] and update their entries, until stopped.
+    fn fetch_worker(&self, receiver: &Mutex<Receiver<FetchMessage>>) {
+        let receiver = receiver.lock().expect("poisoned");
+        while let Ok(FetchMessage::Fetch(job)) = receiver.recv() {
+            let (page, _) = self.fetch_page(&job.url, job.title.as_deref(), job.title_override);
+            let Some(feed_lock) = self.feed_lock(job.feed_name.as_deref()) else {
+                continue;
+            };
+            let feed_path = feed_lock.write().expect("poisoned");
+            let result = Feed::read(&*feed_path).and_then(|mut feed| {
+                feed.set_entry_options(self.entry_options.clone());
+                if feed.update_url(&job.id, &job.url, page) {
+                    feed.save(&self.save_options)
+                } else {
+                    debug!("Entry {} was removed before it was updated", job.id);
+                    Ok(())
+                }
+            });
+            if let Err(err) = result {
+                error!("Unable to update entry {}: {err}", job.id);
+            }
+        }
+        debug!("Fetch worker stopped");
+    }
+
+    /// The path of the feed named `name`, or the default feed if `None`
+    fn feed_lock(&self, name: Option<&str>) -> Option<&RwLock<PathBuf>> {
+        match name {
+            None | Some(DEFAULT_FEED) => Some(&self.feed_path),
+            Some(name) => self.feeds.get(name),
+        }
+    }
+
+    /// The URL of the feed named `name`, or the default feed if `None`
+    fn feed_url(&self, base_url: &str, name: Option<&str>) -> String {
+        match name {
+            None | Some(DEFAULT_FEED) => format!("{base_url}{}", self.feed_route),
+            Some(name) => format!("{base_url}{}/{name}", self.feed_route),
+        }
+    }
+
+    /// Strip the base path from `path`, or `None` if `path` is outside the base path.
+    ///
+    /// The base path itself, with or without a trailing slash, is the index.
+    fn strip_base_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if self.base_path.is_empty() {
+            return Some(path);
+        }
+        match path.strip_prefix(&self.base_path)? {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    /// The path of the feed requested by `path`, if it is one of the feed routes
+    fn route_feed(&self, path: &str) -> Option<&RwLock<PathBuf>> {
+        match path.strip_prefix(&self.feed_route)? {
+            "" => Some(&self.feed_path),
+            name => self.feeds.get(name.strip_prefix('/')?),
+        }
+    }
+
+    /// List the configured feeds and their URLs.
+    ///
+    /// The private token must be supplied in the `token` query parameter.
+    fn feeds(&self, request: &Request) -> Result<HashMap<String, JsonValue>, StatusError> {
+        let query = query_string(request);
+        let token = form_urlencoded::parse(query.as_bytes())
+            .find_map(|(key, value)| (key == "token").then_some(value))
+            .ok_or_else(|| StatusError::new(UNAUTHORIZED, "Missing token"))?;
+        if self.private_token != *token {
+            return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
+        }
+
+        let base_url = self.base_url(request);
+        let feed = |name: &str, url: String| {
+            let map = IntoIterator::into_iter([
+                ("name".to_string(), JsonValue::from(name.to_string())),
+                ("url".to_string(), JsonValue::from(url)),
+            ])
+            .collect();
+            JsonValue::Object(map)
+        };
+        let feeds = std::iter::once(feed(DEFAULT_FEED, self.feed_url(&base_url, None)))
+            .chain(
+                self.feeds
+                    .keys()
+                    .map(|name| feed(name, self.feed_url(&base_url, Some(name)))),
+            )
+            .collect::<Vec<_>>();
+
+        Ok(IntoIterator::into_iter([
+            ("status".to_string(), JsonValue::from("ok".to_string())),
+            ("feeds".to_string(), JsonValue::from(feeds)),
+        ])
+        .collect())
+    }
+
+    /// Count the outcome of a request to add a link for `/metrics`
+    fn count_add(&self, result: &Result<Added, StatusError>) {
+        let counter = match result {
+            Ok(_) => &self.metrics.adds,
+            Err(_) => &self.metrics.add_errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render the metrics in the Prometheus text format.
+    ///
+    /// The private token must be supplied in the `token` query parameter, unless `/metrics` is
+    /// public.
+    fn metrics(&self, request: &Request) -> Result<String, StatusError> {
+        if !self.public_metrics {
+            let query = query_string(request);
+            let token = form_urlencoded::parse(query.as_bytes())
+                .find_map(|(key, value)| (key == "token").then_some(value))
+                .ok_or_else(|| StatusError::new(UNAUTHORIZED, "Missing token"))?;
+            if self.private_token != *token {
+                return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
+            }
+        }
 
-        // Add to the feed
-        let feed_path = self.feed_path.write().expect("poisoned");
+        let mut body = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(String, u64)]| {
+            body.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+            for (labels, value) in values {
+                body.push_str(&format!("{name}{labels} {value}\n"));
+            }
+        };
+        let counter = |counter: &AtomicU64| [(String::new(), counter.load(Ordering::Relaxed))];
+        metric(
+            "feedlynx_adds_total",
+            "counter",
+            "Links added to a feed.",
+            &counter(&self.metrics.adds),
+        );
+        metric(
+            "feedlynx_add_errors_total",
+            "counter",
+            "Requests to add a link that failed, including duplicates.",
+            &counter(&self.metrics.add_errors),
+        );
+        metric(
+            "feedlynx_fetch_failures_total",
+            "counter",
+            "Pages that could not be fetched for metadata.",
+            &counter(&self.metrics.fetch_failures),
+        );
+
+        let feeds = std::iter::once((DEFAULT_FEED, &self.feed_path))
+            .chain(self.feeds.iter().map(|(name, lock)| (name.as_str(), lock)));
+        let mut entries = Vec::new();
+        for (name, lock) in feeds {
+            let count = read_feed(lock)?.entries().len();
+            entries.push((format!("{{feed=\"{name}\"}}"), count as u64));
+        }
+        metric(
+            "feedlynx_feed_entries",
+            "gauge",
+            "Entries in the feed.",
+            &entries,
+        );
+
+        Ok(body)
+    }
+
+    /// Determine if links to `url` can be added according to the allowed and blocked domains
+    fn domain_allowed(&self, url: &URI) -> bool {
+        let Some(host) = url.host() else {
+            return false;
+        };
+        if webpage::host_matches(host, &self.blocked_domains) {
+            return false;
+        }
+        self.allowed_domains.is_empty() || webpage::host_matches(host, &self.allowed_domains)
+    }
+
+    /// Mark an entry as read or unread, returning its id and read state
+    fn mark(
+        &self,
+        request: &Request,
+        body: Result<Vec<u8>, StatusError>,
+    ) -> Result<(String, bool), StatusError> {
+        let fields = self.read_fields(request, body)?;
+
+        let mut token = None;
+        let mut id = None;
+        let mut read = None;
+        let mut feed_name = None;
+
+        fields.into_iter().for_each(|(key, value)| match &*key {
+            "token" => token = Some(value),
+            "id" => id = Some(value),
+            "read" => read = Some(value),
+            "feed" => feed_name = Some(value).filter(|name| !name.is_empty()),
+            _ => {}
+        });
+
+        let token = token.ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing token"))?;
+
+        // Validate token
+        if self.private_token != *token {
+            return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
+        }
+
+        let id = id
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing id"))?;
+        let read = match read.as_deref() {
+            Some("true" | "1") => true,
+            Some("false" | "0") => false,
+            _ => return Err(StatusError::new(BAD_REQUEST, "read must be true or false")),
+        };
+        let feed_lock = self
+            .feed_lock(feed_name.as_deref())
+            .ok_or_else(|| StatusError::new(BAD_REQUEST, "Unknown feed"))?;
+
+        let feed_path = feed_lock.write().expect("poisoned");
         let mut feed = Feed::read(&*feed_path).map_err(|err| {
             error!("Unable to read feed file: {err}");
             StatusError::new(INTERNAL_SERVER_ERROR, "Unable to read feed file")
         })?;
-        feed.add_url(&url, page);
-        feed.trim_entries();
-        feed.save().map_err(|err| {
+        if !feed.set_read(&id, read) {
+            return Err(StatusError::new(NOT_FOUND, "Entry not found"));
+        }
+        feed.trim_entries(&self.trim_options);
+        feed.save(&self.save_options).map_err(|err| {
             error!("Unable to save feed: {err}");
             StatusError::new(INTERNAL_SERVER_ERROR, "Error saving feed file")
-        })
+        })?;
+
+        Ok((id.into_owned(), read))
     }
 
-    fn info(&self, request: &mut Request) -> Result<HashMap<String, JsonValue>, StatusError> {
-        self.validate_request(request)?;
-        let body = read_body(request)?;
+    /// Remove all the entries from a feed, returning how many were removed
+    fn clear(
+        &self,
+        request: &Request,
+        body: Result<Vec<u8>, StatusError>,
+    ) -> Result<usize, StatusError> {
+        let fields = self.read_fields(request, body)?;
 
-        // Parse the form submission and extract the token
         let mut token = None;
+        let mut feed_name = None;
 
-        form_urlencoded::parse(&body).for_each(|(key, value)| match &*key {
+        fields.into_iter().for_each(|(key, value)| match &*key {
             "token" => token = Some(value),
+            "feed" => feed_name = Some(value).filter(|name| !name.is_empty()),
             _ => {}
         });
 
@@ -303,78 +1354,680 @@ impl Server {
             return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
         }
 
-        Ok(IntoIterator::into_iter([
+        let feed_lock = self
+            .feed_lock(feed_name.as_deref())
+            .ok_or_else(|| StatusError::new(BAD_REQUEST, "Unknown feed"))?;
+
+        let feed_path = feed_lock.write().expect("poisoned");
+        let mut feed = Feed::read(&*feed_path).map_err(|err| {
+            error!("Unable to read feed file: {err}");
+            StatusError::new(INTERNAL_SERVER_ERROR, "Unable to read feed file")
+        })?;
+        let removed = feed.clear_entries();
+        feed.save(&self.save_options).map_err(|err| {
+            error!("Unable to save feed: {err}");
+            StatusError::new(INTERNAL_SERVER_ERROR, "Error saving feed file")
+        })?;
+        info!("Cleared {removed} entries from {}", feed_path.display());
+
+        Ok(removed)
+    }
+
+    fn info(
+        &self,
+        request: &Request,
+        body: Result<Vec<u8>, StatusError>,
+    ) -> Result<HashMap<String, JsonValue>, StatusError> {
+        let fields = self.read_fields(request, body)?;
+
+        // Extract the token and limit from the submitted fields
+        let mut token = None;
+        let mut limit = None;
+
+        fields.into_iter().for_each(|(key, value)| match &*key {
+            "token" => token = Some(value),
+            "limit" => limit = Some(value),
+            _ => {}
+        });
+
+        let token = token.ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing token"))?;
+
+        // Validate token
+        if self.private_token != *token {
+            return Err(StatusError::new(UNAUTHORIZED, "Invalid token"));
+        }
+
+        let limit = limit
+            .map(|limit| limit.trim().parse::<usize>())
+            .transpose()
+            .map_err(|_| StatusError::new(BAD_REQUEST, "limit must be a number"))?
+            .map(|limit| limit.min(MAX_RECENT));
+
+        // The number of entries in the default feed added from each source
+        let feed = read_feed(&self.feed_path)?;
+        let sources = feed
+            .source_counts()
+            .into_iter()
+            .map(|(source, count)| (source.to_string(), JsonValue::from(count as f64)))
+            .collect::<HashMap<_, _>>();
+
+        let mut info = IntoIterator::into_iter([
             ("status".to_string(), JsonValue::from("ok".to_string())),
             (
                 "version".to_string(),
                 JsonValue::from(env!("CARGO_PKG_VERSION").to_string()),
             ),
+            ("sources".to_string(), JsonValue::from(sources)),
+            (
+                "updated".to_string(),
+                JsonValue::from(feed.updated().to_rfc3339()),
+            ),
         ])
-        .collect())
+        .collect::<HashMap<_, _>>();
+
+        // The most recently added entries, newest first. Entries are stored oldest first.
+        if let Some(limit) = limit {
+            let recent = feed
+                .entries()
+                .iter()
+                .rev()
+                .take(limit)
+                .map(|entry| {
+                    let url = entry
+                        .links()
+                        .iter()
+                        .find(|link| link.rel() == "alternate")
+                        .map(|link| link.href().to_string())
+                        .unwrap_or_default();
+                    let mut map: HashMap<_, _> = IntoIterator::into_iter([
+                        ("id".to_string(), JsonValue::from(entry.id().to_string())),
+                        (
+                            "title".to_string(),
+                            JsonValue::from(entry.title().as_str().to_string()),
+                        ),
+                        ("url".to_string(), JsonValue::from(url)),
+                        (
+                            "updated".to_string(),
+                            JsonValue::from(entry.updated().to_rfc3339()),
+                        ),
+                    ])
+                    .collect();
+                    if let Some(added) = feed::added_at(entry) {
+                        map.insert("added".to_string(), JsonValue::from(added.to_rfc3339()));
+                    }
+                    JsonValue::Object(map)
+                })
+                .collect::<Vec<_>>();
+            info.insert("recent".to_string(), JsonValue::from(recent));
+        }
+
+        Ok(info)
+    }
+
+    /// Parse the fields submitted in `body`, the body of `request`, in any of the supported
+    /// formats.
+    fn read_fields(
+        &self,
+        request: &Request,
+        body: Result<Vec<u8>, StatusError>,
+    ) -> Result<Fields, StatusError> {
+        let format = self.validate_request(request)?;
+        let body = body?;
+        let owned = |(key, value): (String, String)| (Cow::from(key), Cow::from(value));
+        match format {
+            BodyFormat::Form => Ok(form_urlencoded::parse(&body)
+                .into_owned()
+                .map(owned)
+                .collect()),
+            // Some share tools can only send multipart forms
+            BodyFormat::Multipart(boundary) => multipart::parse(&body, &boundary)
+                .map(|fields| fields.into_iter().map(owned).collect())
+                .ok_or_else(|| StatusError::new(BAD_REQUEST, "Invalid multipart body")),
+            BodyFormat::Json => json_fields(&body)
+                .map(|fields| fields.into_iter().map(owned).collect())
+                .ok_or_else(|| StatusError::new(BAD_REQUEST, "Invalid JSON body")),
+        }
     }
 
-    fn validate_request(&self, request: &Request) -> Result<(), StatusError> {
+    /// Check the Content-Type of `request` and determine the format of its body.
+    fn validate_request(&self, request: &Request) -> Result<BodyFormat, StatusError> {
         // Extract required headers
-        let content_type = request
-            .headers()
-            .iter()
-            .find(|&header| &header.field == CONTENT_TYPE.get().unwrap())
+        let content_type = header_value(request, &CONTENT_TYPE)
             .ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing Content-Type"))?;
-        let Ok(content_type) = content_type.value.as_str().parse::<Mime>() else {
-            return Err(StatusError::new(
-                UNSUPPORTED_MEDIA_TYPE,
-                "Unable to parse content type",
-            ));
-        };
 
-        if content_type.essence_str() != mime::APPLICATION_WWW_FORM_URLENCODED {
+        let (media_type, params) = parse_content_type(content_type);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find_map(|&(param, value)| param.eq_ignore_ascii_case(name).then_some(value))
+        };
+        let is = |mime: &Mime| media_type.eq_ignore_ascii_case(mime.essence_str());
+        let format = if is(&mime::APPLICATION_WWW_FORM_URLENCODED) {
+            BodyFormat::Form
+        } else if is(&mime::MULTIPART_FORM_DATA) {
+            let boundary = param("boundary")
+                .filter(|boundary| !boundary.is_empty())
+                .ok_or_else(|| StatusError::new(BAD_REQUEST, "Missing multipart boundary"))?;
+            BodyFormat::Multipart(boundary.to_string())
+        } else if is(&mime::APPLICATION_JSON) {
+            BodyFormat::Json
+        } else {
             return Err(StatusError::new(
                 UNSUPPORTED_MEDIA_TYPE,
                 "Unsupported media type",
             ));
-        }
+        };
 
-        if content_type
-            .get_param(mime::CHARSET)
-            .map_or(false, |charset| charset != mime::UTF_8)
-        {
+        let charset = param("charset");
+        // ASCII is a subset of UTF-8 so it's decoded the same way
+        if charset.is_some_and(|charset| {
+            !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("us-ascii")
+        }) {
             return Err(StatusError::new(
                 UNSUPPORTED_MEDIA_TYPE,
                 "Unsupported character set",
             ));
         }
 
-        Ok(())
+        Ok(format)
     }
 
-    fn log_request(&self, request: &Request, status: StatusCode) {
-        if log_enabled!(log::Level::Debug) {
+    /// Log `request` and the status and body size in bytes of the response to it
+    fn log_request(&self, request: &Request, status: StatusCode, size: Option<usize>) {
+        // Unknown and empty bodies are logged as `-`
+        let size = || {
+            size.filter(|&size| size > 0)
+                .map(|size| Cow::from(size.to_string()))
+                .unwrap_or_else(|| Cow::from("-"))
+        };
+        if self.access_log {
+            let host = request
+                .remote_addr()
+                .map(|sock| Cow::from(sock.ip().to_string()))
+                .unwrap_or_else(|| Cow::from("-"));
+            info!(
+                target: "access",
+                "{} - - [{}] \"{} {} HTTP/{}\" {} {} \"{}\" \"{}\"",
+                host,
+                Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+                request.method().as_str(),
+                redact_token(request.url()),
+                request.http_version(),
+                status.0,
+                size(),
+                header_value(request, &REFERER).map_or(Cow::from("-"), redact_token),
+                header_value(request, &USER_AGENT).unwrap_or("-")
+            )
+        } else if log_enabled!(log::Level::Debug) {
             let host = request
                 .remote_addr()
                 .map(|sock| Cow::from(sock.to_string()))
                 .unwrap_or_else(|| Cow::from("-"));
-            let user_agent = request.headers().iter().find_map(|header| {
-                (&header.field == USER_AGENT.get().unwrap()).then(|| header.value.as_str())
-            });
+            let user_agent = header_value(request, &USER_AGENT);
             debug!(
-                "{} \"{} {}\" {} \"{}\"",
+                "{} \"{} {}\" {} {} \"{}\"",
                 host,
                 request.method().as_str(),
-                request.url(),
+                redact_token(request.url()),
                 status.0,
+                size(),
                 user_agent.unwrap_or("-")
             )
         }
     }
 
+    /// Trim old entries from each feed, saving those that changed.
+    ///
+    /// Feeds are normally trimmed when a link is added, this allows them to be compacted on
+    /// demand. Errors are logged since there's no request to report them to.
+    pub fn trim_feeds(&self) {
+        let feeds = std::iter::once(&self.feed_path).chain(self.feeds.values());
+        for feed_lock in feeds {
+            let feed_path = feed_lock.write().expect("poisoned");
+            let mut feed = match Feed::read(&*feed_path) {
+                Ok(feed) => feed,
+                Err(err) => {
+                    error!("Unable to read feed file {}: {err}", feed_path.display());
+                    continue;
+                }
+            };
+            let before = feed.entries().len();
+            feed.trim_entries(&self.trim_options);
+            let trimmed = before - feed.entries().len();
+            if trimmed == 0 {
+                continue;
+            }
+            match feed.save(&self.save_options) {
+                Ok(()) => info!("Trimmed {trimmed} entries from {}", feed_path.display()),
+                Err(err) => error!("Unable to save feed {}: {err}", feed_path.display()),
+            }
+        }
+    }
+
     pub fn shutdown(&self) {
-        self.server.unblock();
+        // Each unblock stops one handler thread
+        for server in &self.servers {
+            (0..HANDLER_THREADS).for_each(|_| server.unblock());
+        }
+        // Pages already queued are fetched before the worker stops
+        if let Some((sender, _)) = &self.fetch_queue {
+            let _ = sender.send(FetchMessage::Stop);
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Start building a server for the feed at `feed_path`.
+    ///
+    /// At least one listen address and both tokens must be set before building.
+    pub fn new<P: Into<PathBuf>>(feed_path: P) -> Self {
+        ServerBuilder {
+            addrs: Vec::new(),
+            private_token: None,
+            feed_token: None,
+            feed_path: feed_path.into(),
+            trim_options: TrimOptions::default(),
+            fetch_options: FetchOptions::default(),
+            cors_origins: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Listen on `host` and `port`, in addition to any addresses already added.
+    pub fn listen<H: Into<String>>(mut self, host: H, port: u16) -> Self {
+        self.addrs.push((host.into(), port));
+        self
+    }
+
+    /// Set the token required to add links and make other changes
+    pub fn private_token(mut self, token: PrivateToken) -> Self {
+        self.private_token = Some(token);
+        self
+    }
+
+    /// Set the token in the URL of the feed
+    pub fn feed_token(mut self, token: FeedToken) -> Self {
+        self.feed_token = Some(token);
+        self
+    }
+
+    /// Set which entries are removed when the feeds are trimmed
+    pub fn trim_options(mut self, trim_options: TrimOptions) -> Self {
+        self.trim_options = trim_options;
+        self
+    }
+
+    /// Set how added pages are fetched.
+    ///
+    /// This replaces any timeout or user agent set previously.
+    pub fn fetch_options(mut self, fetch_options: FetchOptions) -> Self {
+        self.fetch_options = fetch_options;
+        self
+    }
+
+    /// Set the time allowed for each request when fetching pages
+    pub fn fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.fetch_options.timeout = timeout;
+        self
+    }
+
+    /// Set the User-Agent sent when fetching pages
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.fetch_options.user_agent = user_agent.into();
+        self
+    }
+
+    /// Set the origins allowed to make cross-origin requests, any origin is allowed when empty
+    pub fn cors_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = origins;
+        self
+    }
+
+    /// Serve HTTPS with `certificate` and `private_key`, both in PEM format.
+    ///
+    /// The certificate may be a chain, starting with the server's certificate.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, certificate: Vec<u8>, private_key: Vec<u8>) -> Self {
+        self.tls = Some(tiny_http::SslConfig {
+            certificate,
+            private_key,
+        });
+        self
+    }
+
+    /// Bind the listen addresses and build the server.
+    pub fn build(self) -> Result<Server, Box<dyn Error + Send + Sync + 'static>> {
+        let private_token = self.private_token.ok_or("no private token")?;
+        let feed_token = self.feed_token.ok_or("no feed token")?;
+        #[cfg(feature = "tls")]
+        let server = match self.tls {
+            Some(tls) => {
+                Server::new_https(&self.addrs, tls, private_token, feed_token, self.feed_path)?
+            }
+            None => Server::new(&self.addrs, private_token, feed_token, self.feed_path)?,
+        };
+        #[cfg(not(feature = "tls"))]
+        let server = Server::new(&self.addrs, private_token, feed_token, self.feed_path)?;
+        let mut server = server
+            .with_fetch_options(self.fetch_options)
+            .with_cors_origins(self.cors_origins);
+        server.trim_options = self.trim_options;
+        Ok(server)
     }
 }
 
-fn read_body(request: &mut Request) -> Result<Vec<u8>, StatusError> {
+impl RunningServer {
+    /// The running server
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Stop handling requests and wait for requests in progress to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.server.shutdown();
+        if thread.join().is_err() {
+            error!("Server thread panicked");
+        }
+    }
+}
+
+impl Drop for RunningServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Bind a server to each of `addrs` with `bind`
+fn bind<A, F>(
+    addrs: &[A],
+    bind: F,
+) -> Result<Vec<tiny_http::Server>, Box<dyn Error + Send + Sync + 'static>>
+where
+    A: ToSocketAddrs,
+    F: FnMut(&A) -> Result<tiny_http::Server, Box<dyn Error + Send + Sync + 'static>>,
+{
+    if addrs.is_empty() {
+        return Err("no listen address".into());
+    }
+    addrs.iter().map(bind).collect()
+}
+
+fn read_feed(feed_path: &RwLock<PathBuf>) -> Result<Feed, StatusError> {
+    let feed_path = feed_path.read().expect("poisoned");
+    Feed::read(&*feed_path).map_err(|err| {
+        error!("Unable to read feed file: {err}");
+        StatusError::new(INTERNAL_SERVER_ERROR, "Unable to read feed file")
+    })
+}
+
+/// The methods supported by `path`, for the Allow header, or `None` if it's not a known route
+fn allowed_methods(path: &str, is_feed: bool) -> Option<&'static str> {
+    match path {
+        _ if is_feed => Some("GET, HEAD"),
+        "/" | "/feeds" | "/metrics" | "/version" => Some("GET"),
+        "/add" => Some("GET, POST, OPTIONS"),
+        "/info" | "/mark" => Some("POST, OPTIONS"),
+        "/clear" => Some("POST"),
+        _ => None,
+    }
+}
+
+/// Split a Content-Type header value into the media type and its parameters.
+///
+/// Clients vary in the case and spacing they use, E.g. Ktor sends
+/// `application/x-www-form-urlencoded; Charset=UTF-8`, so surrounding whitespace and quotes are
+/// removed. Media types and parameter names should be compared case-insensitively.
+fn parse_content_type(value: &str) -> (&str, Vec<(&str, &str)>) {
+    let mut parts = value.split(';');
+    let media_type = parts.next().unwrap_or_default().trim();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+        .collect();
+    (media_type, params)
+}
+
+/// The fields of a JSON object `body`, with values converted to strings as if they were
+/// submitted in a form.
+///
+/// Booleans and numbers are formatted, and arrays of strings are joined with commas, E.g. for
+/// `tags`. Null and nested object values are skipped.
+fn json_fields(body: &[u8]) -> Option<Vec<(String, String)>> {
+    let json = std::str::from_utf8(body).ok()?.parse::<JsonValue>().ok()?;
+    let JsonValue::Object(object) = json else {
+        return None;
+    };
+    let fields = object
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                JsonValue::String(value) => value,
+                JsonValue::Boolean(value) => value.to_string(),
+                JsonValue::Number(value) => value.to_string(),
+                JsonValue::Array(items) => items
+                    .into_iter()
+                    .filter_map(|item| match item {
+                        JsonValue::String(item) => Some(item),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                JsonValue::Null | JsonValue::Object(_) => return None,
+            };
+            Some((key, value))
+        })
+        .collect();
+    Some(fields)
+}
+
+/// Decode the percent-encoded bytes in `path`.
+///
+/// Invalid escapes are left as is. If the decoded path isn't UTF-8 the original is returned so
+/// that it doesn't match any route.
+fn percent_decode(path: &str) -> Cow<'_, str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path);
+    }
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+            _ => None,
+        };
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_or(Cow::Borrowed(path), Cow::Owned)
+}
+
+/// The query string of the URL of `request`, without the leading `?`
+fn query_string(request: &Request) -> &str {
+    request.url().split_once('?').map_or("", |(_, query)| query)
+}
+
+/// `url` with the value of the `token` query parameter replaced so the private token isn't logged
+fn redact_token(url: &str) -> Cow<'_, str> {
+    let is_token = |param: &str| {
+        form_urlencoded::parse(param.as_bytes())
+            .next()
+            .is_some_and(|(key, _)| key == "token")
+    };
+    match url.split_once('?') {
+        Some((path, query)) if query.split('&').any(is_token) => {
+            let query = query
+                .split('&')
+                .map(|param| {
+                    if is_token(param) {
+                        "token=REDACTED"
+                    } else {
+                        param
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+            Cow::from(format!("{path}?{query}"))
+        }
+        _ => Cow::from(url),
+    }
+}
+
+/// Determine if a GET request to /add should add the link instead of showing the form
+fn is_submit(request: &Request) -> bool {
+    form_urlencoded::parse(query_string(request).as_bytes())
+        .any(|(key, value)| key == "submit" && (value == "1" || value == "true"))
+}
+
+/// Build the response to a request to add a link
+fn add_response(result: Result<Added, StatusError>, json: bool) -> Response<io::Cursor<Vec<u8>>> {
+    let added = match result {
+        Ok(added) => added,
+        // Errors are in the same format as success so clients only need to handle one
+        Err(err) if json => return json_response(Err(err)),
+        Err(StatusError(status, error)) => {
+            return Response::from_string(format!("Failed: {error}\n")).with_status_code(status)
+        }
+    };
+    let status = if added.pending { ACCEPTED } else { CREATED };
+    // NOTE(unwrap): the count is always ASCII
+    let entry_count =
+        Header::from_bytes("X-Feedlynx-Entry-Count", added.entry_count.to_string()).unwrap();
+
+    let response = if json {
+        // Metadata is fetched later for pending links, so it hasn't been fetched yet
+        let metadata_fetched = !added.pending && added.fetch_error.is_none();
+        let mut map: HashMap<_, _> = IntoIterator::into_iter([
+            ("status".to_string(), JsonValue::from("added".to_string())),
+            ("id".to_string(), JsonValue::from(added.id)),
+            ("url".to_string(), JsonValue::from(added.url)),
+            (
+                "metadata_fetched".to_string(),
+                JsonValue::from(metadata_fetched),
+            ),
+        ])
+        .collect();
+        if let Some(error) = added.fetch_error {
+            map.insert("fetch_error".to_string(), JsonValue::from(error));
+        }
+        let json = JsonValue::Object(map);
+        // NOTE(unwrap): io::Error should not happen when writing to a String
+        Response::from_string(tinyjson::stringify(&json).unwrap())
+            .with_header(header(&JSON_CONTENT_TYPE))
+    } else {
+        Response::from_string(format!("Added\nid: {}\nurl: {}\n", added.id, added.url))
+    };
+    response.with_status_code(status).with_header(entry_count)
+}
+
+/// Build a JSON response from the result of a JSON endpoint
+fn json_response(
+    result: Result<HashMap<String, JsonValue>, StatusError>,
+) -> Response<io::Cursor<Vec<u8>>> {
+    let (status, map) = match result {
+        Ok(map) => (200, map),
+        Err(StatusError(status, error)) => (
+            status.0,
+            IntoIterator::into_iter([
+                ("status".to_string(), JsonValue::from("error".to_string())),
+                ("message".to_string(), JsonValue::from(error.to_string())),
+            ])
+            .collect(),
+        ),
+    };
+    let json = JsonValue::Object(map);
+    // NOTE(unwrap): io::Error should not happen when writing to a String
+    Response::from_string(tinyjson::stringify(&json).unwrap())
+        .with_header(header(&JSON_CONTENT_TYPE))
+        .with_status_code(status)
+}
+
+/// Read the body of `request` on another thread, which must be received within `timeout`.
+///
+/// tiny_http doesn't expose the socket so a timeout can't be set on reads. A client that stops
+/// sending the body would block a read indefinitely, so it's done on a thread of its own rather
+/// than tying up a handler. If the body isn't received in time `None` is returned and the
+/// request is left to that thread, which responds with 408 Request Timeout when the read
+/// returns, I.e. when the client sends more or closes the connection. `reader` is held until
+/// then.
+fn receive_body(
+    request: Request,
+    timeout: Duration,
+    reader: BodyReader,
+) -> Option<(Request, Result<Vec<u8>, StatusError>)> {
+    // With a zero sized channel the send fails, returning the request, if the receiver has
+    // given up and gone away
+    let (sender, receiver) = mpsc::sync_channel(0);
+    thread::spawn(move || {
+        let _reader = reader;
+        let mut request = request;
+        let body = read_body(&mut request, timeout);
+        if let Err(mpsc::SendError((request, _))) = sender.send((request, body)) {
+            // Server::respond can't be used from this thread. The response is an error so the
+            // headers it adds aren't needed.
+            let response = Response::from_string("Failed: Timed out reading POST body\n")
+                .with_status_code(REQUEST_TIMEOUT);
+            if let Err(err) = request.respond(response) {
+                error!("Failed to send response: {err}");
+            }
+        }
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(received) => Some(received),
+        Err(_) => {
+            error!("Timed out reading POST body");
+            None
+        }
+    }
+}
+
+/// One of the [MAX_BODY_READERS] threads allowed to read request bodies, released when dropped.
+struct BodyReader(Arc<AtomicUsize>);
+
+impl BodyReader {
+    /// Count another reader in `readers`, if there are fewer than [MAX_BODY_READERS].
+    fn acquire(readers: &Arc<AtomicUsize>) -> Option<BodyReader> {
+        readers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_BODY_READERS).then_some(count + 1)
+            })
+            .ok()?;
+        Some(BodyReader(Arc::clone(readers)))
+    }
+}
+
+impl Drop for BodyReader {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Read the body of `request`, which must be received within `timeout`.
+fn read_body(request: &mut Request, timeout: Duration) -> Result<Vec<u8>, StatusError> {
     let mut buf = [0; 8 * 1024];
     let mut body = Vec::new();
+    let deadline = Instant::now() + timeout;
     let reader = request.as_reader();
     loop {
         match reader.read(&mut buf) {
@@ -398,11 +2051,203 @@ fn read_body(request: &mut Request) -> Result<Vec<u8>, StatusError> {
             error!("{msg}");
             return Err(StatusError::new(PAYLOAD_TOO_LARGE, msg));
         }
+        // The deadline is also checked between reads, which stops clients from dribbling the
+        // body in slowly to tie up the reading thread.
+        if Instant::now() > deadline {
+            let msg = "Timed out reading POST body";
+            error!("{msg}");
+            return Err(StatusError::new(REQUEST_TIMEOUT, msg));
+        }
     }
 
     Ok(body)
 }
 
+/// Build the response body for the feed file.
+///
+/// Gzipped feeds are sent as is if the client accepts gzip, otherwise they are decompressed.
+/// Responses to HEAD requests (`head`) have no body but the same Content-Length as the
+/// equivalent GET.
+fn feed_response(
+    mut file: File,
+    gzipped: bool,
+    accepts_gzip: bool,
+    head: bool,
+) -> io::Result<ResponseBox> {
+    // Files are streamed from disk so they don't need to be held in memory
+    if !gzipped {
+        return Ok(Response::from_file(file).boxed());
+    }
+
+    let vary = header(&VARY_ACCEPT_ENCODING);
+    if accepts_gzip {
+        Ok(Response::from_file(file)
+            .with_header(header(&CONTENT_ENCODING_GZIP))
+            .with_header(vary)
+            .boxed())
+    } else if head {
+        // The last four bytes of a gzip file are the length of the uncompressed data (modulo
+        // 2^32), which avoids decompressing the feed just to find its length.
+        let mut trailer = [0; 4];
+        file.seek(io::SeekFrom::End(-4))?;
+        file.read_exact(&mut trailer)?;
+        let len = u32::from_le_bytes(trailer) as usize;
+        Ok(Response::new(StatusCode(200), vec![vary], io::empty(), Some(len), None).boxed())
+    } else {
+        // The decompressor works on a slice so this holds the compressed and decompressed feed in
+        // memory at the same time. Feeds are trimmed so this is expected to be small, and most
+        // feed readers accept gzip, which is streamed from the file.
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Response::from_data(gzip::decompress(&data)?)
+            .with_header(vary)
+            .boxed())
+    }
+}
+
+/// The changes made to a feed when serving it
+struct FeedFilter<'a> {
+    /// Only include entries with this tag
+    tag: Option<&'a str>,
+    /// Only include entries updated after this time
+    since: Option<DateTime<FixedOffset>>,
+    order: EntryOrder,
+}
+
+impl FeedFilter<'_> {
+    /// Determine if the feed is served as it's stored, so it can be sent straight from the file.
+    fn is_nop(&self) -> bool {
+        self.tag.is_none() && self.since.is_none() && self.order == EntryOrder::Oldest
+    }
+}
+
+/// The feed with the changes of `filter` applied.
+///
+/// The lock is only held while the feed is read. A gzipped feed is compressed again for clients
+/// that accept it.
+fn filtered_feed_response(
+    feed_lock: &RwLock<PathBuf>,
+    filter: &FeedFilter,
+    accepts_gzip: bool,
+    options: &SaveOptions,
+) -> Result<ResponseBox, crate::Error> {
+    let (mut feed, gzipped) = {
+        let feed_path = feed_lock.read().expect("poisoned");
+        (Feed::read(&*feed_path)?, gzip::is_gzip_path(&feed_path))
+    };
+    if let Some(tag) = filter.tag {
+        feed.retain_tag(tag);
+    }
+    if let Some(since) = filter.since {
+        feed.retain_since(since);
+    }
+    feed.order_entries(filter.order);
+    let xml = feed.to_xml(options)?;
+
+    if !gzipped {
+        return Ok(Response::from_data(xml).boxed());
+    }
+    let vary = header(&VARY_ACCEPT_ENCODING);
+    if accepts_gzip {
+        Ok(Response::from_data(gzip::compress(&xml))
+            .with_header(header(&CONTENT_ENCODING_GZIP))
+            .with_header(vary)
+            .boxed())
+    } else {
+        Ok(Response::from_data(xml).with_header(vary).boxed())
+    }
+}
+
+/// Parse the RFC 3339 (ISO 8601) time of the `since` query parameter.
+///
+/// A `+` in the offset is decoded to a space if the client didn't percent-encode it, so that's
+/// tried too.
+fn parse_since(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_rfc3339(&value.replace(' ', "+")))
+        .ok()
+}
+
+/// A weak ETag for the feed file derived from its modification time and size.
+///
+/// This avoids reading the file to hash its content. It's weak because the same ETag is used
+/// for gzip and decompressed responses.
+fn etag(metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "W/\"{:x}.{:x}-{:x}\"",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+/// Determine if `etag` matches any of the values of an If-None-Match header.
+///
+/// If-None-Match uses the weak comparison so the `W/` prefix is ignored.
+fn etag_matches(etag: &str, if_none_match: &str) -> bool {
+    fn opaque(tag: &str) -> &str {
+        tag.trim().trim_start_matches("W/")
+    }
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// The value of the first `field` header of `request`.
+fn header_value<'a>(
+    request: &'a Request,
+    field: &'static OnceLock<HeaderField>,
+) -> Option<&'a str> {
+    header_values(request, field).next()
+}
+
+/// The values of each `field` header of `request`, in order.
+fn header_values<'a>(
+    request: &'a Request,
+    field: &'static OnceLock<HeaderField>,
+) -> impl Iterator<Item = &'a str> {
+    // NOTE(unwrap): the header fields are set before requests are handled
+    let field = field.get().unwrap();
+    request
+        .headers()
+        .iter()
+        .filter(move |header| &header.field == field)
+        .map(|header| header.value.as_str())
+}
+
+/// A pre-built response header.
+fn header(header: &OnceLock<Header>) -> Header {
+    // NOTE(unwrap): the headers are set before requests are handled
+    header.get().cloned().unwrap()
+}
+
+/// Determine if the client accepts gzip content encoding from the Accept-Encoding header.
+fn accepts_gzip(request: &Request) -> bool {
+    header_values(request, &ACCEPT_ENCODING)
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            // A quality of zero means not acceptable
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case("gzip") && !rejected
+        })
+}
+
+/// Determine if the client would like a JSON response from the Accept header.
+fn accepts_json(request: &Request) -> bool {
+    header_values(request, &ACCEPT)
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_range| media_range.trim().parse::<Mime>().ok())
+        .any(|mime| mime.essence_str() == mime::APPLICATION_JSON)
+}
+
 /// Compare mtime and If-Modified-Since value to determine if content has changed.
 ///
 /// The values are compared as seconds since the UNIX epoch because SystemTime
@@ -4,10 +4,23 @@ pub use unix::SignalHandle;
 #[cfg(windows)]
 pub use windows::SignalHandle;
 
+/// What a received signal asks the process to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Signal {
+    /// Stop the server: `SIGINT`, `SIGTERM`, `SIGHUP`, or Ctrl-C on Windows.
+    Shutdown,
+    /// Trim old entries from the feeds and save them: `SIGUSR1`.
+    Trim,
+}
+
 #[cfg(unix)]
 mod unix {
     use std::{io, mem, ptr};
 
+    use super::Signal;
+
+    /// Blocks SIGINT, SIGTERM, SIGHUP, and SIGUSR1 so that they can be waited on by a dedicated
+    /// thread.
     pub struct SignalHandle(Sigset);
 
     impl SignalHandle {
@@ -16,14 +29,17 @@ mod unix {
             set.addsig(libc::SIGINT)?;
             set.addsig(libc::SIGTERM)?;
             set.addsig(libc::SIGHUP)?;
+            set.addsig(libc::SIGUSR1)?;
 
             set.setsigmask()?;
             Ok(SignalHandle(set))
         }
 
-        pub fn block_until_signalled(&self) -> io::Result<()> {
-            self.0.wait()?;
-            Ok(())
+        pub fn block_until_signalled(&self) -> io::Result<Signal> {
+            match self.0.wait()? {
+                libc::SIGUSR1 => Ok(Signal::Trim),
+                _ => Ok(Signal::Shutdown),
+            }
         }
     }
 
@@ -118,6 +134,10 @@ mod unix {
 
 #[cfg(windows)]
 mod windows {
+    use super::Signal;
+
+    /// Registers a console control handler so that Ctrl-C (and Ctrl-Break, console close,
+    /// etc.) can be waited on by a dedicated thread.
     pub struct SignalHandle {
         _initialised: (),
     }
@@ -128,8 +148,9 @@ mod windows {
             Ok(SignalHandle { _initialised: () })
         }
 
-        pub fn block_until_signalled(&self) -> io::Result<()> {
-            unsafe { block_ctrl_c() }
+        pub fn block_until_signalled(&self) -> io::Result<Signal> {
+            unsafe { block_ctrl_c() }?;
+            Ok(Signal::Shutdown)
         }
     }
 
@@ -1,14 +1,139 @@
-use std::{fmt, io};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io, thread};
 
-use html5gum::{HtmlString, IoReader, Tokenizer};
-use log::trace;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use encoding_rs::{Encoding, UTF_8};
+use html5gum::{DefaultEmitter, HtmlString, IoReader, Tokenizer};
+use log::{debug, trace};
+use mime::Mime;
 use minreq::URL;
+use tinyjson::JsonValue;
+use uriparse::{Host, Scheme, URIReference, URI};
+
+/// The longest the server will wait when asked to retry a request via Retry-After
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// Phrases in the title or description that indicate a bot challenge or login wall, lowercase
+const CHALLENGE_PHRASES: &[&str] = &[
+    "just a moment",
+    "are you a robot",
+    "are you a human",
+    "verify you are human",
+    "attention required",
+    "access denied",
+    "sign in to continue",
+    "log in to continue",
+    "please enable javascript",
+];
 
 #[derive(Default)]
 pub struct WebPage {
     pub title: Option<String>,
     pub description: Option<String>,
     pub author: Option<String>,
+    /// The Open Graph type of the page, E.g. `article` or `video.other`
+    pub og_type: Option<String>,
+    /// When the page was published, if known
+    pub published: Option<DateTime<FixedOffset>>,
+    /// URL of an image representing the page, from `og:image`
+    pub image: Option<String>,
+    /// A video representing the page, from `og:video`
+    pub video: Option<Video>,
+    /// The short URL of the page, from `<link rel="shortlink">`
+    pub shortlink: Option<String>,
+    /// URL of the page's oEmbed endpoint, from `<link type="application/json+oembed">`
+    pub oembed_url: Option<String>,
+    /// HTML that embeds the content of the page, from its oEmbed endpoint
+    pub embed_html: Option<String>,
+    /// The page appears to be a bot challenge or login wall, so the metadata likely describes
+    /// that rather than the requested page
+    pub low_confidence: bool,
+}
+
+/// A video from the Open Graph metadata of a page
+pub struct Video {
+    pub url: String,
+    /// The MIME type of the video from `og:video:type`, E.g. `video/mp4` or `text/html` for a
+    /// player page
+    pub mime_type: Option<String>,
+}
+
+/// The default maximum number of characters of a description stored in an entry summary
+pub const DEFAULT_MAX_SUMMARY: usize = 2000;
+
+impl WebPage {
+    /// Extract the metadata from an HTML document that has already been fetched.
+    ///
+    /// The document is already text so any charset it declares is ignored. Relative URLs in the
+    /// metadata are only resolved if the document has a `<base href>`.
+    pub fn from_html(html: &str) -> WebPage {
+        // NOTE(unwrap): reading from a slice can't fail
+        extract_meta_data(html.as_bytes(), Some(UTF_8), None).unwrap()
+    }
+
+    /// Truncate the description to at most `max_chars` characters, including a trailing ellipsis.
+    pub fn truncate_description(&mut self, max_chars: usize) {
+        let Some(description) = &mut self.description else {
+            return;
+        };
+        if let Some((end, _)) = description.char_indices().nth(max_chars) {
+            // Make room for the ellipsis
+            let end = description[..end]
+                .char_indices()
+                .next_back()
+                .map_or(0, |(index, _)| index);
+            description.truncate(end);
+            description.push('…');
+        }
+    }
+
+    /// Trim the title, discarding it if it has fewer than `min_chars` characters.
+    ///
+    /// This stops blank or junk titles, like those on some challenge pages, from taking the
+    /// place of the title supplied when adding the link.
+    pub fn trim_title(&mut self, min_chars: usize) {
+        self.title = self.title.take().and_then(|title| {
+            let trimmed = title.trim();
+            (!trimmed.is_empty() && trimmed.chars().count() >= min_chars)
+                .then(|| trimmed.to_string())
+        });
+    }
+}
+
+/// The default number of bytes of a page that will be parsed for metadata
+pub const DEFAULT_MAX_PAGE_SIZE: u64 = 512 * 1024; // 512KiB
+
+/// The default time allowed for each request when fetching a page
+pub const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The default minimum number of characters of a page title
+pub const DEFAULT_MIN_TITLE_LENGTH: usize = 1;
+
+/// The default number of redirects followed when fetching a page
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Options that control how web pages are fetched
+#[derive(Clone)]
+pub struct FetchOptions {
+    /// Allow fetching pages from private, loopback, and link-local addresses
+    pub allow_private_hosts: bool,
+    /// The maximum number of bytes of the page body to parse for metadata
+    pub max_page_size: u64,
+    /// The time allowed for each request, with one second resolution
+    pub timeout: Duration,
+    /// Titles with fewer characters than this, after trimming, are treated as missing
+    pub min_title_length: usize,
+    /// The maximum number of redirects followed for each request
+    pub max_redirects: usize,
+    /// Hosts, including their subdomains, whose oEmbed endpoints are fetched to embed pages
+    pub oembed_hosts: Vec<String>,
+    /// The User-Agent header sent with each request
+    pub user_agent: String,
 }
 
 #[derive(Debug)]
@@ -20,27 +145,37 @@ pub enum WebPageError {
         reason_phrase: String,
     },
     NotHtml,
+    InvalidUrl,
+    PrivateAddress(IpAddr),
 }
 
-pub fn fetch<U: Into<URL>>(url: U) -> Result<WebPage, WebPageError> {
-    let resp = minreq::get(url)
-        .with_timeout(15)
-        .with_max_redirects(10)
-        .with_max_headers_size(8192) // GitHub sends back responses with more than 4KiB of headers
-        .with_max_status_line_length(1024)
-        // DuckDuckBot/1.1; (+http://duckduckgo.com/duckduckbot.html)
-        .with_header(
-            "User-Agent",
-            format!(
-                "{}/{}; (+{})",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION"),
-                env!("CARGO_PKG_HOMEPAGE"),
-            ),
-        )
-        .send_lazy()?;
+pub fn fetch<U: Into<URL>>(url: U, options: &FetchOptions) -> Result<WebPage, WebPageError> {
+    let url = url.into();
+    let mut resp = send(&url, options)?;
 
-    if resp.status_code != 200 {
+    // Rate limited or temporarily unavailable: retry once if the server told us when to
+    if matches!(resp.status_code, 429 | 503) {
+        let retry_after = resp
+            .headers
+            .get("retry-after")
+            .and_then(|value| parse_retry_after(value, SystemTime::now()));
+        if let Some(wait) = retry_after {
+            let wait = wait.min(MAX_RETRY_AFTER);
+            debug!(
+                "{url} responded with {}, retrying in {}s",
+                resp.status_code,
+                wait.as_secs()
+            );
+            drop(resp);
+            thread::sleep(wait);
+            resp = send(&url, options)?;
+        }
+    }
+
+    // Bot challenges and login walls are often served with these statuses. The page is still
+    // parsed so that callers can decide whether to use the low confidence metadata.
+    let blocked = matches!(resp.status_code, 403 | 429);
+    if resp.status_code != 200 && !blocked {
         return Err(WebPageError::Unsuccessful {
             status_code: resp.status_code,
             reason_phrase: resp.reason_phrase,
@@ -48,32 +183,366 @@ pub fn fetch<U: Into<URL>>(url: U) -> Result<WebPage, WebPageError> {
     }
 
     // Check that response is HTML
-    if !resp
-        .headers
-        .get("content-type")
-        .map_or(false, |content_type| content_type.contains("text/html"))
-    {
+    let content_type = resp.headers.get("content-type");
+    if !content_type.is_some_and(|content_type| content_type.contains("text/html")) {
         return Err(WebPageError::NotHtml);
     }
+    let encoding = content_type
+        .and_then(|content_type| content_type.parse::<Mime>().ok())
+        .and_then(|mime| {
+            mime.get_param(mime::CHARSET)
+                .and_then(|charset| Encoding::for_label(charset.as_str().as_bytes()))
+        });
+
+    // Metadata lives in the <head> so there's no need to parse the whole of large pages. The
+    // final URL, after any redirects, is the base for relative URLs in the page.
+    let page_url = resp.url.clone();
+    let mut page = extract_meta_data(
+        Read::take(resp, options.max_page_size),
+        encoding,
+        Some(&page_url),
+    )?;
+    page.low_confidence |= blocked;
+    page.trim_title(options.min_title_length);
+
+    let oembed_url = page
+        .oembed_url
+        .as_deref()
+        .filter(|_| is_oembed_host(&page_url, &options.oembed_hosts));
+    if let Some(oembed_url) = oembed_url {
+        match fetch_oembed(oembed_url, options) {
+            Ok(html) => page.embed_html = html,
+            Err(err) => debug!("Unable to fetch oEmbed for {page_url}: {err}"),
+        }
+    }
+    Ok(page)
+}
+
+/// Fetch the oEmbed response from `url` and return the HTML to embed, if it has any.
+fn fetch_oembed(url: &str, options: &FetchOptions) -> Result<Option<String>, WebPageError> {
+    let resp = send(url, options)?;
+    if resp.status_code != 200 {
+        return Err(WebPageError::Unsuccessful {
+            status_code: resp.status_code,
+            reason_phrase: resp.reason_phrase,
+        });
+    }
+    let mut body = Vec::new();
+    Read::take(resp, options.max_page_size).read_to_end(&mut body)?;
+
+    let Some(json) = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| body.parse::<JsonValue>().ok())
+    else {
+        debug!("oEmbed response from {url} is not JSON");
+        return Ok(None);
+    };
+    Ok(json_str(&json, "html")
+        .map(str::trim)
+        .filter(|html| !html.is_empty())
+        .map(String::from))
+}
+
+/// Determine if the host of `url` is one of `hosts`, or a subdomain of one.
+fn is_oembed_host(url: &str, hosts: &[String]) -> bool {
+    URI::try_from(url)
+        .ok()
+        .and_then(|uri| uri.host().map(|host| host_matches(host, hosts)))
+        .unwrap_or(false)
+}
+
+/// Determine if `host` is one of `domains`, or a subdomain of one. Case is ignored.
+pub(crate) fn host_matches(host: &Host, domains: &[String]) -> bool {
+    let host = host.to_string().to_ascii_lowercase();
+    domains.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        host == allowed
+            || host
+                .strip_suffix(allowed.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Determine if the page looks like a bot challenge or login wall from its title and
+/// description.
+fn is_challenge_page(page: &WebPage) -> bool {
+    [&page.title, &page.description]
+        .into_iter()
+        .flatten()
+        .map(|text| text.to_lowercase())
+        .any(|text| CHALLENGE_PHRASES.iter().any(|phrase| text.contains(phrase)))
+}
+
+/// Send a GET request for `url`, following up to `options.max_redirects` redirects.
+///
+/// Redirects are followed here rather than by minreq so that the host of each hop is checked
+/// with [check_public_host] before connecting to it. The `url` of the response is that of the
+/// last hop.
+fn send(url: &str, options: &FetchOptions) -> Result<minreq::ResponseLazy, WebPageError> {
+    send_checked(url, options, |url| {
+        if options.allow_private_hosts {
+            return resolve_host(url);
+        }
+        check_public_host(url)
+    })
+}
+
+/// [send], resolving and checking the URL of each hop with `check` before connecting to it.
+///
+/// Each request is made through a [pinned_proxy] to the addresses returned by `check`.
+fn send_checked(
+    url: &str,
+    options: &FetchOptions,
+    check: impl Fn(&str) -> Result<Vec<SocketAddr>, WebPageError>,
+) -> Result<minreq::ResponseLazy, WebPageError> {
+    let mut url = url.to_string();
+    let mut redirects = 0;
+    loop {
+        let addrs = check(&url)?;
+        let proxy = pinned_proxy(addrs, options.timeout)?;
+        let resp = request(url.clone(), options)
+            .with_proxy(proxy)
+            .send_lazy()?;
+        let location = matches!(resp.status_code, 301 | 302 | 303 | 307 | 308)
+            .then(|| resp.headers.get("location"))
+            .flatten();
+        let Some(location) = location else {
+            return Ok(resp);
+        };
+
+        if redirects == options.max_redirects {
+            return Err(WebPageError::Http(minreq::Error::TooManyRedirections));
+        }
+        redirects += 1;
+        let next = resolve_url(&url, location).ok_or(WebPageError::InvalidUrl)?;
+        debug!("{url} redirected to {next}");
+        url = next;
+    }
+}
+
+fn request(url: URL, options: &FetchOptions) -> minreq::Request {
+    minreq::get(url)
+        .with_timeout(options.timeout.as_secs())
+        .with_follow_redirects(false)
+        .with_max_headers_size(8192) // GitHub sends back responses with more than 4KiB of headers
+        .with_max_status_line_length(1024)
+        .with_header("User-Agent", &options.user_agent)
+}
 
-    let tokenizer = Tokenizer::new(IoReader::new(resp));
+/// The default User-Agent, in the style of crawlers like
+/// `DuckDuckBot/1.1; (+http://duckduckgo.com/duckduckbot.html)`
+pub fn default_user_agent() -> String {
+    format!(
+        "{}/{}; (+{})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE"),
+    )
+}
 
-    extract_meta_data(tokenizer)
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            allow_private_hosts: false,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            timeout: DEFAULT_FETCH_TIMEOUT,
+            min_title_length: DEFAULT_MIN_TITLE_LENGTH,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            oembed_hosts: Vec::new(),
+            user_agent: default_user_agent(),
+        }
+    }
 }
 
-fn extract_meta_data(
-    tokenizer: Tokenizer<IoReader<minreq::ResponseLazy>>,
+/// Ensure that the host of `url` does not resolve to a private address.
+///
+/// This stops the server being used to probe services on the internal network. Returns the
+/// addresses the host resolved to, which are the ones that must be connected to.
+fn check_public_host(url: &str) -> Result<Vec<SocketAddr>, WebPageError> {
+    let addrs = resolve_host(url)?;
+    match addrs.iter().map(SocketAddr::ip).find(is_private_addr) {
+        Some(ip) => Err(WebPageError::PrivateAddress(ip)),
+        None => Ok(addrs),
+    }
+}
+
+/// Resolve the host of `url` to the addresses to connect to.
+fn resolve_host(url: &str) -> Result<Vec<SocketAddr>, WebPageError> {
+    let uri = URI::try_from(url).map_err(|_| WebPageError::InvalidUrl)?;
+    let port = uri.port().unwrap_or(match uri.scheme() {
+        Scheme::HTTPS => 443,
+        _ => 80,
+    });
+    match uri.host() {
+        Some(Host::IPv4Address(ip)) => Ok(vec![SocketAddr::new(IpAddr::V4(*ip), port)]),
+        Some(Host::IPv6Address(ip)) => Ok(vec![SocketAddr::new(IpAddr::V6(*ip), port)]),
+        Some(Host::RegisteredName(name)) => Ok((name.as_str(), port).to_socket_addrs()?.collect()),
+        None => Err(WebPageError::InvalidUrl),
+    }
+}
+
+/// Connect to one of `addrs` and start a proxy on the loopback interface that tunnels a
+/// connection to it.
+///
+/// minreq resolves the host of a URL itself when connecting, so if the DNS answer changed after
+/// [check_public_host], E.g. in a DNS rebinding attack, it could connect to a private address.
+/// Requests are sent through this proxy instead, which uses the checked addresses whatever host
+/// it's asked for.
+fn pinned_proxy(addrs: Vec<SocketAddr>, timeout: Duration) -> io::Result<minreq::Proxy> {
+    let server = connect(&addrs, timeout)?;
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let proxy = minreq::Proxy::new(listener.local_addr()?.to_string()).map_err(io::Error::other)?;
+    thread::spawn(move || {
+        if let Err(err) = tunnel(listener, server, timeout) {
+            debug!("Proxy to {addrs:?} failed: {err}");
+        }
+    });
+    Ok(proxy)
+}
+
+/// Connect to the first of `addrs` that accepts the connection.
+fn connect(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Accept a connection on `listener` and, after its CONNECT request, tunnel it to `server`.
+fn tunnel(listener: TcpListener, server: TcpStream, timeout: Duration) -> io::Result<()> {
+    // Don't wait forever if the request fails before it connects
+    let deadline = Instant::now() + timeout;
+    listener.set_nonblocking(true)?;
+    let mut client = loop {
+        match listener.accept() {
+            Ok((client, _)) => break client,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5))
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    drop(listener);
+    client.set_nonblocking(false)?;
+    client.set_read_timeout(Some(timeout))?;
+
+    // The host asked for has already been resolved, so the request is skipped. Nothing is sent
+    // after it until the response.
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") {
+        if client.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        request.push(byte[0]);
+    }
+    server.set_read_timeout(Some(timeout))?;
+    client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+
+    let (mut client_reader, mut server_writer) = (client.try_clone()?, server.try_clone()?);
+    let upload = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut server_writer);
+        let _ = server_writer.shutdown(Shutdown::Write);
+    });
+    let (mut server_reader, mut client_writer) = (server, client);
+    let _ = io::copy(&mut server_reader, &mut client_writer);
+    let _ = client_writer.shutdown(Shutdown::Write);
+    let _ = upload.join();
+    Ok(())
+}
+
+fn is_private_addr(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ipv4(&ip),
+            None => is_private_ipv6(ip),
+        },
+    }
+}
+
+fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || a == 0 // this network: 0.0.0.0/8
+        || (a == 100 && (b & 0xc0) == 64) // shared address space: 100.64.0.0/10
+        || (a == 192 && b == 0 && c == 0) // IETF protocol assignments: 192.0.0.0/24
+        || (a == 198 && (b & 0xfe) == 18) // benchmarking: 198.18.0.0/15
+}
+
+fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || (first & 0xfe00) == 0xfc00 // unique local: fc00::/7
+        || (first & 0xffc0) == 0xfe80 // link-local: fe80::/10
+}
+
+/// Parse the value of a Retry-After header into the duration to wait.
+///
+/// The value is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(now).unwrap_or_default()),
+    }
+}
+
+/// Extract metadata from the HTML read from `reader`.
+///
+/// Text is decoded using `encoding`, which is typically from the Content-Type header. If that's
+/// not supplied the charset declared in the document is used, falling back on UTF-8.
+///
+/// Relative URLs in the metadata are resolved against the `<base href>` of the document, or
+/// `page_url`, the URL the page was fetched from.
+fn extract_meta_data<R: Read>(
+    reader: R,
+    mut encoding: Option<&'static Encoding>,
+    page_url: Option<&str>,
 ) -> Result<WebPage, WebPageError> {
+    // The encoding from the Content-Type header takes precedence over the document
+    let header_encoding = encoding.is_some();
+    // Switching states means the content of <title> and <script> is emitted as text instead of
+    // being tokenised as markup
+    let mut emitter = DefaultEmitter::default();
+    emitter.switch_states(true);
+    let tokenizer = Tokenizer::new_with_emitter(IoReader::new(reader), emitter);
+
     let mut title = None;
     let mut description = None;
     let mut author = None;
+    let mut og_type = None;
+    let mut published = None;
+    let mut image = None;
+    let mut oembed_url = None;
+    let mut video = None;
+    let mut video_type = None;
+    let mut shortlink = None;
+    let mut base_href = None;
 
     let property_attr = HtmlString(b"property".to_vec());
     let content_attr = HtmlString(b"content".to_vec());
     let name_attr = HtmlString(b"name".to_vec());
+    let type_attr = HtmlString(b"type".to_vec());
+    let charset_attr = HtmlString(b"charset".to_vec());
+    let http_equiv_attr = HtmlString(b"http-equiv".to_vec());
+    let href_attr = HtmlString(b"href".to_vec());
+    let rel_attr = HtmlString(b"rel".to_vec());
 
     let mut title_tag = String::new();
     let mut in_title = false;
+    let mut json_ld = String::new();
+    let mut in_json_ld = false;
     for token in tokenizer {
         let token = token?; // TODO: If we already have a title or description when hitting an error then maybe just return what we have so far
 
@@ -81,11 +550,38 @@ fn extract_meta_data(
             // <meta>
             html5gum::Token::StartTag(tag) if *tag.name == b"meta" => {
                 trace!("Tag {:?}", tag);
+                // <meta charset="..."> or <meta http-equiv="Content-Type" content="...">
+                if !header_encoding {
+                    let charset = tag
+                        .attributes
+                        .get(&charset_attr)
+                        .and_then(|charset| Encoding::for_label(charset));
+                    let http_equiv = tag
+                        .attributes
+                        .get(&http_equiv_attr)
+                        .is_some_and(|value| value.eq_ignore_ascii_case(b"content-type"));
+                    let charset = charset.or_else(|| {
+                        http_equiv
+                            .then(|| tag.attributes.get(&content_attr))
+                            .flatten()
+                            .and_then(|value| std::str::from_utf8(value).ok())
+                            .and_then(|value| value.parse::<Mime>().ok())
+                            .and_then(|mime| {
+                                mime.get_param(mime::CHARSET).and_then(|charset| {
+                                    Encoding::for_label(charset.as_str().as_bytes())
+                                })
+                            })
+                    });
+                    if charset.is_some() {
+                        encoding = charset;
+                    }
+                }
+
                 let content = tag
                     .attributes
                     .get(&content_attr)
-                    .and_then(|v| std::str::from_utf8(v).ok());
-                let Some(content) = content.map(str::trim) else {
+                    .map(|v| decode(encoding, v));
+                let Some(content) = content.as_deref().map(str::trim) else {
                     // If content isn't present then no point checking the other stuff
                     trace!("content missing or invalid");
                     continue;
@@ -95,28 +591,133 @@ fn extract_meta_data(
                 match property.map(|v| v.as_slice()) {
                     Some(b"og:title") => set_if_longer(&mut title, content),
                     Some(b"og:description") => set_if_longer(&mut description, content),
+                    Some(b"og:type") if !content.is_empty() => og_type = Some(content.to_string()),
+                    Some(b"og:image" | b"og:image:url")
+                        if image.is_none() && !content.is_empty() =>
+                    {
+                        image = Some(content.to_string())
+                    }
+                    Some(b"og:video" | b"og:video:url" | b"og:video:secure_url")
+                        if video.is_none() && !content.is_empty() =>
+                    {
+                        video = Some(content.to_string())
+                    }
+                    Some(b"og:video:type") if video_type.is_none() && !content.is_empty() => {
+                        video_type = Some(content.to_string())
+                    }
+                    // This is meant to be a profile URL but is sometimes the author's name
+                    Some(b"article:author") if !is_url(content) => {
+                        set_if_longer(&mut author, content)
+                    }
+                    // Twitter cards are specified with name but some sites use property
+                    Some(b"twitter:title") => set_if_longer(&mut title, content),
+                    Some(b"twitter:description") => set_if_longer(&mut description, content),
                     Some(_) => {}
                     // Check for <meta name="description" content="...">
                     // <meta name="author" content="...">
+                    // <meta name="twitter:title" content="...">
                     None => {
                         let name = tag.attributes.get(&name_attr);
                         match name.map(|s| s.as_slice()) {
                             Some(b"author") => set_if_longer(&mut author, content),
+                            // Byline, E.g. <meta name="byl" content="By Jane Smith">
+                            Some(b"byl") => set_if_longer(&mut author, strip_by(content)),
                             Some(b"description") => set_if_longer(&mut description, content),
+                            Some(b"twitter:title") => set_if_longer(&mut title, content),
+                            Some(b"twitter:description") => {
+                                set_if_longer(&mut description, content)
+                            }
                             _ => {}
                         }
                     }
                 }
             }
+            // <link type="application/json+oembed" href="..."> and <link rel="shortlink"
+            // href="..."> only the first of each is used
+            html5gum::Token::StartTag(tag) if *tag.name == b"link" => {
+                let href = tag
+                    .attributes
+                    .get(&href_attr)
+                    .map(|href| decode(encoding, href).trim().to_string())
+                    .filter(|href| !href.is_empty());
+                let is_oembed = tag
+                    .attributes
+                    .get(&type_attr)
+                    .is_some_and(|ty| ty.eq_ignore_ascii_case(b"application/json+oembed"));
+                let rel = tag
+                    .attributes
+                    .get(&rel_attr)
+                    .map(|rel| decode(encoding, rel));
+                if is_oembed && oembed_url.is_none() {
+                    oembed_url = href;
+                } else if shortlink.is_none()
+                    && rel.is_some_and(|rel| {
+                        rel.split_ascii_whitespace()
+                            .any(|rel| rel.eq_ignore_ascii_case("shortlink"))
+                    })
+                {
+                    shortlink = href;
+                }
+            }
+            // <base href="..."> only the first one is used
+            html5gum::Token::StartTag(tag) if *tag.name == b"base" && base_href.is_none() => {
+                base_href = tag
+                    .attributes
+                    .get(&href_attr)
+                    .map(|href| decode(encoding, href).trim().to_string());
+            }
             // <title>
             html5gum::Token::StartTag(tag) if *tag.name == b"title" => in_title = true,
             html5gum::Token::EndTag(tag) if *tag.name == b"title" => {
                 in_title = false;
             }
             html5gum::Token::String(text) if in_title => {
-                if let Ok(text) = std::str::from_utf8(&text) {
-                    title_tag.push_str(text);
+                title_tag.push_str(&decode(encoding, &text));
+            }
+            // <script type="application/ld+json">
+            html5gum::Token::StartTag(tag) if *tag.name == b"script" => {
+                in_json_ld = tag
+                    .attributes
+                    .get(&type_attr)
+                    .is_some_and(|ty| ty.eq_ignore_ascii_case(b"application/ld+json"));
+            }
+            html5gum::Token::EndTag(tag) if *tag.name == b"script" && in_json_ld => {
+                in_json_ld = false;
+                let json = json_ld.parse::<JsonValue>().ok();
+                if let Some(item) = json.as_ref().and_then(find_article) {
+                    if let Some(headline) =
+                        json_str(item, "headline").or_else(|| json_str(item, "name"))
+                    {
+                        set_if_longer(&mut title, headline);
+                    }
+                    if let Some(desc) = json_str(item, "description") {
+                        set_if_longer(&mut description, desc);
+                    }
+                    if let Some(name) = json_get(item, "author").and_then(json_ld_author) {
+                        set_if_longer(&mut author, name);
+                    }
+                    if published.is_none() {
+                        published = json_str(item, "datePublished").and_then(parse_date);
+                    }
                 }
+                json_ld.clear();
+            }
+            html5gum::Token::String(text) if in_json_ld => {
+                json_ld.push_str(&decode(encoding, &text));
+            }
+            // Stop at </head> or <body> (the end tag of head is optional) if the title and
+            // description have been found. Any later metadata is unlikely to be better.
+            html5gum::Token::EndTag(tag)
+                if *tag.name == b"head" && found_essentials(&title, &title_tag, &description) =>
+            {
+                trace!("found title and description in head, stopping");
+                break;
+            }
+            html5gum::Token::StartTag(tag)
+                if *tag.name == b"body" && found_essentials(&title, &title_tag, &description) =>
+            {
+                trace!("found title and description before body, stopping");
+                break;
             }
             _ => {}
         }
@@ -126,13 +727,137 @@ fn extract_meta_data(
         set_if_longer(&mut title, &title_tag)
     }
 
-    Ok(WebPage {
+    // The base href may itself be relative to the page
+    let base = match (base_href, page_url) {
+        (Some(href), Some(page_url)) => resolve_url(page_url, &href),
+        (Some(href), None) => Some(href),
+        (None, page_url) => page_url.map(String::from),
+    };
+    if let Some(base) = &base {
+        for url in [&mut image, &mut oembed_url, &mut video, &mut shortlink]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(resolved) = resolve_url(base, url) {
+                *url = resolved;
+            }
+        }
+    }
+
+    let mut page = WebPage {
         title,
         description,
         author,
+        og_type,
+        published,
+        image,
+        video: video.map(|url| Video {
+            url,
+            mime_type: video_type,
+        }),
+        shortlink,
+        oembed_url,
+        embed_html: None,
+        low_confidence: false,
+    };
+    page.low_confidence = is_challenge_page(&page);
+    Ok(page)
+}
+
+/// Resolve `url`, which may be relative, against the absolute URL `base`.
+///
+/// Returns `None` if either URL is invalid.
+fn resolve_url(base: &str, url: &str) -> Option<String> {
+    let base = URI::try_from(base).ok()?;
+    let reference = URIReference::try_from(url).ok()?;
+    Some(base.resolve(&reference).to_string())
+}
+
+/// Decode text from the page, replacing malformed sequences
+fn decode<'a>(encoding: Option<&'static Encoding>, bytes: &'a [u8]) -> Cow<'a, str> {
+    encoding
+        .unwrap_or(UTF_8)
+        .decode_without_bom_handling(bytes)
+        .0
+}
+
+/// Find the first Article-like object in JSON-LD data.
+///
+/// The data may be a single object, an array of objects, or wrapped in a `@graph`.
+fn find_article(value: &JsonValue) -> Option<&JsonValue> {
+    match value {
+        JsonValue::Array(items) => items.iter().find_map(find_article),
+        JsonValue::Object(obj) => {
+            if let Some(graph) = obj.get("@graph") {
+                return find_article(graph);
+            }
+            let is_article = match obj.get("@type") {
+                Some(JsonValue::String(ty)) => is_article_type(ty),
+                Some(JsonValue::Array(types)) => types
+                    .iter()
+                    .any(|ty| matches!(ty, JsonValue::String(ty) if is_article_type(ty))),
+                _ => false,
+            };
+            is_article.then_some(value)
+        }
+        _ => None,
+    }
+}
+
+fn is_article_type(ty: &str) -> bool {
+    ty.ends_with("Article") || matches!(ty, "BlogPosting" | "VideoObject" | "WebPage")
+}
+
+fn json_get<'a>(value: &'a JsonValue, key: &str) -> Option<&'a JsonValue> {
+    match value {
+        JsonValue::Object(obj) => obj.get(key),
+        _ => None,
+    }
+}
+
+fn json_str<'a>(value: &'a JsonValue, key: &str) -> Option<&'a str> {
+    match json_get(value, key) {
+        Some(JsonValue::String(s)) if !s.trim().is_empty() => Some(s.trim()),
+        _ => None,
+    }
+}
+
+/// Extract the author name, which may be a string, a Person object, or an array of them.
+fn json_ld_author(value: &JsonValue) -> Option<&str> {
+    match value {
+        JsonValue::String(name) if !name.trim().is_empty() => Some(name.trim()),
+        JsonValue::Object(_) => json_str(value, "name"),
+        JsonValue::Array(authors) => authors.iter().find_map(json_ld_author),
+        _ => None,
+    }
+}
+
+/// Parse an ISO 8601 date or date and time
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value).ok().or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date| date.and_utc().fixed_offset())
     })
 }
 
+fn is_url(value: &str) -> bool {
+    value.starts_with("http://") || value.starts_with("https://")
+}
+
+/// Strip a leading "By" from a byline
+fn strip_by(byline: &str) -> &str {
+    match byline.get(..3) {
+        Some(by) if by.eq_ignore_ascii_case("by ") => byline[3..].trim_start(),
+        _ => byline,
+    }
+}
+
+fn found_essentials(title: &Option<String>, title_tag: &str, description: &Option<String>) -> bool {
+    (title.is_some() || !title_tag.trim().is_empty()) && description.is_some()
+}
+
 pub(crate) fn set_if_longer(value: &mut Option<String>, candidate: &str) {
     match value {
         Some(existing) if candidate.len() > existing.len() => {
@@ -143,6 +868,62 @@ pub(crate) fn set_if_longer(value: &mut Option<String>, candidate: &str) {
     }
 }
 
+/// Escape text for inclusion in HTML content or a quoted attribute value.
+pub(crate) fn escape_html(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::from(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::from(escaped)
+}
+
+/// Remove anything that looks like an HTML tag from `text`.
+///
+/// A `<` is only treated as the start of a tag when followed by a letter, `/`, or `!`, so text
+/// like `a < b` is left alone.
+pub(crate) fn strip_tags(text: &str) -> Cow<'_, str> {
+    let is_tag_start = |rest: &str| {
+        rest.strip_prefix('<')
+            .and_then(|rest| rest.chars().next())
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!')
+    };
+    if !text
+        .match_indices('<')
+        .any(|(i, _)| is_tag_start(&text[i..]))
+    {
+        return Cow::from(text);
+    }
+
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        stripped.push_str(&rest[..start]);
+        if is_tag_start(&rest[start..]) {
+            match rest[start..].find('>') {
+                Some(end) => rest = &rest[start + end + 1..],
+                // Unterminated tag, drop the remainder
+                None => rest = "",
+            }
+        } else {
+            stripped.push('<');
+            rest = &rest[start + 1..];
+        }
+    }
+    stripped.push_str(rest);
+    Cow::from(stripped)
+}
+
 impl From<minreq::Error> for WebPageError {
     fn from(err: minreq::Error) -> Self {
         WebPageError::Http(err)
@@ -168,8 +949,677 @@ impl fmt::Display for WebPageError {
                 "HTTP request was unsuccessful: {reason_phrase} ({status_code})"
             ),
             WebPageError::NotHtml => f.write_str("Response was not HTML"),
+            WebPageError::InvalidUrl => f.write_str("Invalid URL"),
+            WebPageError::PrivateAddress(ip) => {
+                write!(f, "Refusing to fetch from private address {ip}")
+            }
         }
     }
 }
 
 impl std::error::Error for WebPageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::JoinHandle;
+
+    type TestResponse = tiny_http::Response<io::Cursor<Vec<u8>>>;
+
+    /// Serve `responses` in order, one for each request, from a server on a local port.
+    ///
+    /// Returns the URL of the server and a handle that resolves to the headers of each request
+    /// once all the responses have been sent.
+    fn serve(responses: Vec<TestResponse>) -> (String, JoinHandle<Vec<Vec<tiny_http::Header>>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr());
+        let handle = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let request = server.recv().unwrap();
+                    let headers = request.headers().to_vec();
+                    request.respond(response).unwrap();
+                    headers
+                })
+                .collect()
+        });
+        (url, handle)
+    }
+
+    /// A response with the HTML `body`
+    fn html(body: &str) -> TestResponse {
+        tiny_http::Response::from_string(body).with_header(
+            "Content-Type: text/html"
+                .parse::<tiny_http::Header>()
+                .unwrap(),
+        )
+    }
+
+    /// The value of the header `field` in `headers`
+    fn header_value<'a>(headers: &'a [tiny_http::Header], field: &'static str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|header| header.field.equiv(field))
+            .map(|header| header.value.as_str())
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = SystemTime::now();
+        assert_eq!(parse_retry_after("5", now), Some(Duration::from_secs(5)));
+        assert_eq!(
+            parse_retry_after(&httpdate::fmt_http_date(now + Duration::from_secs(60)), now)
+                .map(|wait| wait.as_secs()),
+            Some(59) // the HTTP date drops sub-second precision
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_fetch_retry_after() {
+        let (url, handle) = serve(vec![
+            tiny_http::Response::from_string("Slow down")
+                .with_status_code(429)
+                .with_header("Retry-After: 1".parse::<tiny_http::Header>().unwrap()),
+            html("<title>Retried</title>"),
+        ]);
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            ..Default::default()
+        };
+        let page = fetch(url, &options).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Retried"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_user_agent() {
+        let (url, handle) = serve(vec![html("<title>Agent</title>")]);
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            user_agent: "TestAgent/1.0".to_string(),
+            ..Default::default()
+        };
+        fetch(url, &options).unwrap();
+        let requests = handle.join().unwrap();
+        assert_eq!(
+            header_value(&requests[0], "User-Agent"),
+            Some("TestAgent/1.0")
+        );
+    }
+
+    #[test]
+    fn test_fetch_max_redirects() {
+        let redirect = |location: &str| {
+            tiny_http::Response::from_string("")
+                .with_status_code(302)
+                .with_header(
+                    format!("Location: {location}")
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                )
+        };
+        // Redirect twice, then respond with the page. The first fetch gives up after the
+        // second redirect.
+        let (url, handle) = serve(vec![
+            redirect("/1"),
+            redirect("/2"),
+            redirect("/1"),
+            redirect("/2"),
+            html("<title>Redirected</title>"),
+        ]);
+        let url = format!("{url}0");
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            max_redirects: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            fetch(url.clone(), &options),
+            Err(WebPageError::Http(minreq::Error::TooManyRedirections))
+        ));
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            max_redirects: 2,
+            ..Default::default()
+        };
+        let page = fetch(url, &options).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Redirected"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_timeout() {
+        // Accept the connection but never respond
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(3));
+            drop(stream);
+        });
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        assert!(fetch(url, &options).is_err());
+        assert!(start.elapsed() < Duration::from_secs(3));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_max_page_size() {
+        let mut page = String::from("<html><head><title>Big</title></head><body>");
+        page.push_str(&"<p>filler</p>".repeat(100));
+        page.push_str(r#"<meta name="description" content="Too far"></body></html>"#);
+        let (url, handle) = serve(vec![html(&page)]);
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            max_page_size: 256,
+            ..Default::default()
+        };
+        let page = fetch(url, &options).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Big"));
+        assert_eq!(page.description, None);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_private_addresses() {
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "169.254.169.254",
+            "192.168.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.254",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+        for ip in private {
+            assert!(is_private_addr(&ip.parse().unwrap()), "{ip} is private");
+        }
+        let public = [
+            "93.184.215.14",
+            "100.128.0.1",
+            "192.0.2.1",
+            "198.20.0.1",
+            "2606:2800:21f:cb07:6820:80da:af6b:8b2c",
+        ];
+        for ip in public {
+            assert!(!is_private_addr(&ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[test]
+    fn test_fetch_redirect_private_host() {
+        let private = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let private_url = format!("http://{}/", private.server_addr());
+        let (public_url, handle) = serve(vec![tiny_http::Response::from_string("")
+            .with_status_code(302)
+            .with_header(
+                format!("Location: {private_url}")
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            )]);
+
+        // Treat the first server as public, the redirect to the other is checked as usual
+        let options = FetchOptions::default();
+        let check = |url: &str| {
+            if url == public_url {
+                return resolve_host(url);
+            }
+            check_public_host(url)
+        };
+        assert!(matches!(
+            send_checked(&public_url, &options, check),
+            Err(WebPageError::PrivateAddress(_))
+        ));
+        handle.join().unwrap();
+        let second = private.recv_timeout(Duration::from_millis(100)).unwrap();
+        assert!(second.is_none(), "the private server was requested");
+    }
+
+    #[test]
+    fn test_pinned_proxy() {
+        let (url, handle) = serve(vec![tiny_http::Response::from_string("pinned")]);
+
+        // The proxy connects to the address it was given, not the host requested
+        let proxy = pinned_proxy(resolve_host(&url).unwrap(), Duration::from_secs(5)).unwrap();
+        let resp = minreq::get("http://pinned.invalid/")
+            .with_proxy(proxy)
+            .send()
+            .unwrap();
+        assert_eq!(resp.as_str().unwrap(), "pinned");
+        let requests = handle.join().unwrap();
+        assert_eq!(header_value(&requests[0], "Host"), Some("pinned.invalid"));
+    }
+
+    #[test]
+    fn test_fetch_private_host() {
+        let options = FetchOptions::default();
+        for url in [
+            "http://127.0.0.1:8001/",
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+        ] {
+            match fetch(url, &options) {
+                Err(WebPageError::PrivateAddress(_)) => {}
+                Err(err) => panic!("unexpected error fetching {url}: {err}"),
+                Ok(_) => panic!("fetching {url} should fail"),
+            }
+        }
+    }
+
+    // The body is effectively endless, so this would not finish if parsing continued past the
+    // head once title and description were found.
+    #[test]
+    fn test_stop_after_head() {
+        let head = r#"<html><head><title>Title</title><meta name="description" content="Description"></head><body>"#;
+        let body = io::repeat(b'x').take(1 << 40);
+        let page = extract_meta_data(head.as_bytes().chain(body), None, None).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Title"));
+        assert_eq!(page.description.as_deref(), Some("Description"));
+    }
+
+    #[test]
+    fn test_twitter_card() {
+        let page = WebPage::from_html(
+            r#"<html><head>
+            <title>Site</title>
+            <meta name="twitter:card" content="summary">
+            <meta name="twitter:title" content="Twitter title">
+            <meta name="twitter:description" content="Twitter description">
+            </head></html>"#,
+        );
+        assert_eq!(page.title.as_deref(), Some("Twitter title"));
+        assert_eq!(page.description.as_deref(), Some("Twitter description"));
+    }
+
+    #[test]
+    fn test_twitter_card_does_not_override_longer_og() {
+        let page = WebPage::from_html(
+            r#"<html><head>
+            <meta property="og:title" content="The longer Open Graph title">
+            <meta name="twitter:title" content="Short">
+            </head></html>"#,
+        );
+        assert_eq!(page.title.as_deref(), Some("The longer Open Graph title"));
+    }
+
+    #[test]
+    fn test_author() {
+        let page = WebPage::from_html(
+            r#"<html><head><meta name="author" content="Jane Smith"></head></html>"#,
+        );
+        assert_eq!(page.author.as_deref(), Some("Jane Smith"));
+
+        let page = WebPage::from_html(
+            r#"<html><head><meta property="article:author" content="Jane Smith"></head></html>"#,
+        );
+        assert_eq!(page.author.as_deref(), Some("Jane Smith"));
+
+        let page = WebPage::from_html(
+            r#"<html><head><meta property="article:author" content="https://example.com/jane"></head></html>"#,
+        );
+        assert_eq!(page.author, None);
+
+        let page = WebPage::from_html(
+            r#"<html><head><meta name="byl" content="By Jane Smith"></head></html>"#,
+        );
+        assert_eq!(page.author.as_deref(), Some("Jane Smith"));
+    }
+
+    #[test]
+    fn test_json_ld() {
+        let page = WebPage::from_html(
+            r#"<html><head><title>Site Name</title>
+            <script type="application/ld+json">
+            {
+                "@context": "https://schema.org",
+                "@type": "NewsArticle",
+                "headline": "Headline from JSON-LD",
+                "description": "Description from JSON-LD",
+                "author": [{"@type": "Person", "name": "Jane Smith"}],
+                "datePublished": "2024-06-24T08:52:11+10:00"
+            }
+            </script></head></html>"#,
+        );
+        assert_eq!(page.title.as_deref(), Some("Headline from JSON-LD"));
+        assert_eq!(
+            page.description.as_deref(),
+            Some("Description from JSON-LD")
+        );
+        assert_eq!(page.author.as_deref(), Some("Jane Smith"));
+        assert_eq!(
+            page.published,
+            DateTime::parse_from_rfc3339("2024-06-24T08:52:11+10:00").ok()
+        );
+    }
+
+    #[test]
+    fn test_json_ld_graph() {
+        let page = WebPage::from_html(
+            r#"<html><head>
+            <meta property="og:title" content="A longer title from Open Graph">
+            <script type="application/ld+json">
+            {
+                "@context": "https://schema.org",
+                "@graph": [
+                    {"@type": "WebSite", "name": "Site"},
+                    {"@type": ["BlogPosting"], "headline": "Short", "author": "Jane Smith", "datePublished": "2024-06-24"}
+                ]
+            }
+            </script></head></html>"#,
+        );
+        assert_eq!(
+            page.title.as_deref(),
+            Some("A longer title from Open Graph")
+        );
+        assert_eq!(page.author.as_deref(), Some("Jane Smith"));
+        assert_eq!(
+            page.published,
+            DateTime::parse_from_rfc3339("2024-06-24T00:00:00Z").ok()
+        );
+    }
+
+    #[test]
+    fn test_encoding_from_header() {
+        let html = b"<html><head><title>Caf\xe9</title></head></html>";
+        let page = extract_meta_data(&html[..], Encoding::for_label(b"iso-8859-1"), None).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Café"));
+    }
+
+    #[test]
+    fn test_encoding_from_meta() {
+        let html = b"<html><head><meta charset=\"iso-8859-1\"><title>Caf\xe9</title><meta name=\"description\" content=\"Cr\xe8me br\xfbl\xe9e\"></head></html>";
+        let page = extract_meta_data(&html[..], None, None).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Café"));
+        assert_eq!(page.description.as_deref(), Some("Crème brûlée"));
+
+        let html = b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\"><title>\x93\xfa\x96\x7b</title></head></html>";
+        let page = extract_meta_data(&html[..], None, None).unwrap();
+        assert_eq!(page.title.as_deref(), Some("日本"));
+    }
+
+    #[test]
+    fn test_from_html_ignores_charset() {
+        let page = WebPage::from_html(
+            r#"<html><head><meta charset="windows-1252"><title>Café</title></head></html>"#,
+        );
+        assert_eq!(page.title.as_deref(), Some("Café"));
+    }
+
+    #[test]
+    fn test_encoding_unknown() {
+        let html = b"<html><head><title>Caf\xe9</title></head></html>";
+        let page = extract_meta_data(&html[..], None, None).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Caf\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_relative_og_image() {
+        let html = r#"<html><head>
+            <title>Post</title>
+            <meta property="og:image" content="../images/cover.png">
+            </head></html>"#;
+        let page =
+            extract_meta_data(html.as_bytes(), None, Some("https://example.com/a/b/post")).unwrap();
+        assert_eq!(
+            page.image.as_deref(),
+            Some("https://example.com/a/images/cover.png")
+        );
+
+        // <base href> takes precedence over the page URL and may itself be relative
+        let html = r#"<html><head>
+            <base href="/static/">
+            <base href="https://ignored.example.com/">
+            <meta property="og:image" content="cover.png">
+            </head></html>"#;
+        let page =
+            extract_meta_data(html.as_bytes(), None, Some("https://example.com/a/post")).unwrap();
+        assert_eq!(
+            page.image.as_deref(),
+            Some("https://example.com/static/cover.png")
+        );
+
+        // Absolute URLs are unchanged
+        let html = r#"<meta property="og:image" content="https://cdn.example.com/cover.png">"#;
+        let page = extract_meta_data(html.as_bytes(), None, Some("https://example.com/")).unwrap();
+        assert_eq!(
+            page.image.as_deref(),
+            Some("https://cdn.example.com/cover.png")
+        );
+    }
+
+    #[test]
+    fn test_oembed_link() {
+        let html = r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+            <link rel="alternate" type="application/json+oembed" href="/oembed?url=post">
+            <link rel="alternate" type="application/json+oembed" href="/second">
+            </head></html>"#;
+        let page =
+            extract_meta_data(html.as_bytes(), None, Some("https://example.com/post")).unwrap();
+        assert_eq!(
+            page.oembed_url.as_deref(),
+            Some("https://example.com/oembed?url=post")
+        );
+        assert_eq!(page.embed_html, None);
+    }
+
+    #[test]
+    fn test_og_video() {
+        let html = r#"<html><head>
+            <link rel="shortlink" href="/?p=123">
+            <meta property="og:video" content="/media/clip.mp4">
+            <meta property="og:video:secure_url" content="https://example.com/ignored.mp4">
+            <meta property="og:video:type" content="video/mp4">
+            </head></html>"#;
+        let page = extract_meta_data(
+            html.as_bytes(),
+            None,
+            Some("https://example.com/posts/clip"),
+        )
+        .unwrap();
+        let video = page.video.unwrap();
+        assert_eq!(video.url, "https://example.com/media/clip.mp4");
+        assert_eq!(video.mime_type.as_deref(), Some("video/mp4"));
+        assert_eq!(
+            page.shortlink.as_deref(),
+            Some("https://example.com/?p=123")
+        );
+
+        let page = WebPage::from_html(
+            r#"<meta property="og:video:url" content="https://example.com/player">"#,
+        );
+        let video = page.video.unwrap();
+        assert_eq!(video.url, "https://example.com/player");
+        assert_eq!(video.mime_type, None);
+        assert_eq!(page.shortlink, None);
+    }
+
+    #[test]
+    fn test_is_oembed_host() {
+        let hosts = ["vimeo.com".to_string()];
+        assert!(is_oembed_host("https://vimeo.com/123", &hosts));
+        assert!(is_oembed_host("https://player.VIMEO.com/123", &hosts));
+        assert!(!is_oembed_host("https://notvimeo.com/123", &hosts));
+        assert!(!is_oembed_host("https://example.com/", &hosts));
+        assert!(!is_oembed_host("https://vimeo.com/123", &[]));
+    }
+
+    #[test]
+    fn test_fetch_oembed() {
+        let (url, handle) = serve(vec![
+            html(r#"<title>Video</title><link type="application/json+oembed" href="/oembed">"#),
+            tiny_http::Response::from_string(
+                r#"{"type":"video","html":"<iframe src=\"/embed\"></iframe>"}"#,
+            )
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap(),
+            ),
+        ]);
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            oembed_hosts: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        };
+        let page = fetch(url, &options).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Video"));
+        assert_eq!(
+            page.embed_html.as_deref(),
+            Some(r#"<iframe src="/embed"></iframe>"#)
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_og_image() {
+        let page = WebPage::from_html(
+            r#"<html><head>
+            <meta property="og:image" content="https://example.com/first.png">
+            <meta property="og:image" content="https://example.com/second.png">
+            </head></html>"#,
+        );
+        assert_eq!(page.image.as_deref(), Some("https://example.com/first.png"));
+    }
+
+    #[test]
+    fn test_og_type_article() {
+        let page = WebPage::from_html(
+            r#"<html><head><meta property="og:type" content="article"><title>Post</title></head></html>"#,
+        );
+        assert_eq!(page.og_type.as_deref(), Some("article"));
+    }
+
+    #[test]
+    fn test_og_type_video() {
+        let page = WebPage::from_html(
+            r#"<html><head><meta property="og:type" content="video.other"><title>Clip</title></head></html>"#,
+        );
+        assert_eq!(page.og_type.as_deref(), Some("video.other"));
+    }
+
+    #[test]
+    fn test_og_type_missing() {
+        let page = WebPage::from_html(r#"<html><head><title>Plain</title></head></html>"#);
+        assert_eq!(page.og_type, None);
+        assert_eq!(page.title.as_deref(), Some("Plain"));
+    }
+
+    #[test]
+    fn test_truncate_description() {
+        let mut page = WebPage {
+            description: Some("ä".repeat(5000)),
+            ..Default::default()
+        };
+        page.truncate_description(DEFAULT_MAX_SUMMARY);
+        let description = page.description.unwrap();
+        assert_eq!(description.chars().count(), DEFAULT_MAX_SUMMARY);
+        assert!(description.ends_with("ää…"));
+
+        let mut page = WebPage {
+            description: Some("Short".to_string()),
+            ..Default::default()
+        };
+        page.truncate_description(5);
+        assert_eq!(page.description.as_deref(), Some("Short"));
+        page.truncate_description(4);
+        assert_eq!(page.description.as_deref(), Some("Sho…"));
+    }
+
+    #[test]
+    fn test_trim_title() {
+        let page = |title: &str| WebPage {
+            title: Some(title.to_string()),
+            ..Default::default()
+        };
+
+        let mut blank = page(" \n\t ");
+        blank.trim_title(DEFAULT_MIN_TITLE_LENGTH);
+        assert_eq!(blank.title, None);
+
+        let mut padded = page("  Title\n");
+        padded.trim_title(DEFAULT_MIN_TITLE_LENGTH);
+        assert_eq!(padded.title.as_deref(), Some("Title"));
+
+        let mut short = page(" ab ");
+        short.trim_title(3);
+        assert_eq!(short.title, None);
+        let mut short = page("äbc");
+        short.trim_title(3);
+        assert_eq!(short.title.as_deref(), Some("äbc"));
+    }
+
+    #[test]
+    fn test_strip_tags() {
+        assert_eq!(strip_tags("No tags"), "No tags");
+        assert_eq!(strip_tags("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2");
+        assert_eq!(
+            strip_tags("<p>Some <b>bold</b> text</p><!-- comment -->"),
+            "Some bold text"
+        );
+        assert_eq!(strip_tags("Hi<script>alert(1)</script>"), "Hialert(1)");
+        assert_eq!(strip_tags("Unterminated <a href="), "Unterminated ");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("plain"), "plain");
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_is_challenge_page() {
+        let page = WebPage::from_html("<html><head><title>Just a moment...</title></head></html>");
+        assert!(is_challenge_page(&page));
+        let page = WebPage::from_html(
+            r#"<html><head><title>Example</title><meta name="description" content="Sign in to continue to Example"></head></html>"#,
+        );
+        assert!(is_challenge_page(&page));
+        let page = WebPage::from_html("<html><head><title>A moment in time</title></head></html>");
+        assert!(!is_challenge_page(&page));
+    }
+
+    #[test]
+    fn test_fetch_forbidden_low_confidence() {
+        let (url, handle) = serve(vec![html("<title>Forbidden</title>").with_status_code(403)]);
+
+        let options = FetchOptions {
+            allow_private_hosts: true,
+            ..Default::default()
+        };
+        let page = fetch(url, &options).unwrap();
+        assert_eq!(page.title.as_deref(), Some("Forbidden"));
+        assert!(page.low_confidence);
+        handle.join().unwrap();
+    }
+}
@@ -1,16 +1,19 @@
 use std::{
     collections::HashMap,
     fs,
-    io::Cursor,
+    io::{Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
     path::{Path, PathBuf},
-    process::Child,
+    process::{Child, Command, Stdio},
     time::Duration,
 };
 
 use atom_syndication as atom;
+use chrono::DateTime;
 use form_urlencoded as form;
 
 use feedlynx::base62::base62;
+use feedlynx::{Feed, FeedToken, PrivateToken, RunningServer, SaveOptions, ServerBuilder};
 use minreq::Request;
 use tinyjson::{JsonParser, JsonValue};
 
@@ -89,6 +92,8 @@ fn server() {
 
                 let body = res.as_str().unwrap();
                 assert!(body.contains("Feed available at"));
+                // QR code of the feed URL
+                assert!(body.contains("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox="));
                 break;
             }
             Err(err) => {
@@ -115,6 +120,66 @@ fn server() {
         env!("CARGO_PKG_VERSION")
     );
 
+    // The version is available without a token
+    let res = minreq::get(format!("http://{}/version", address))
+        .send()
+        .expect("GET /version failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(
+        res.headers.get("content-type").map(String::as_str),
+        Some("application/json")
+    );
+    assert_eq!(
+        res.as_str().unwrap(),
+        format!(r#"{{"version":"{}"}}"#, env!("CARGO_PKG_VERSION"))
+    );
+
+    // Every response says which version answered it, including errors
+    for path in [
+        "/version".to_string(),
+        "/missing".to_string(),
+        format!("/feed/{FEED_TOKEN}"),
+    ] {
+        let res = minreq::get(format!("http://{}{}", address, path))
+            .send()
+            .expect("GET failed");
+        assert_eq!(
+            res.headers.get("x-feedlynx-version").map(String::as_str),
+            Some(env!("CARGO_PKG_VERSION")),
+            "{path}"
+        );
+    }
+
+    // Any origin is allowed by default
+    let res = prepare_get_info(PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /info failed");
+    assert_eq!(
+        res.headers
+            .get("access-control-allow-origin")
+            .map(String::as_str),
+        Some("*")
+    );
+
+    // Check CORS preflight requests are answered
+    for path in ["/add", "/info"] {
+        let res = minreq::Request::new(
+            minreq::Method::Options,
+            format!("http://{}{}", address, path),
+        )
+        .with_header("Origin", "moz-extension://abcd")
+        .with_header("Access-Control-Request-Method", "POST")
+        .with_header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .expect("OPTIONS failed");
+        assert_eq!(res.status_code, 204);
+        let header = |name| res.headers.get(name).map(String::as_str);
+        assert_eq!(header("access-control-allow-origin"), Some("*"));
+        assert_eq!(header("access-control-allow-methods"), Some("POST"));
+        assert_eq!(header("access-control-allow-headers"), Some("Content-Type"));
+    }
+
     // Fetch info from the server with charset
     let info = get_info(Some("utf-8"), &address);
     assert!(info.is_object());
@@ -146,7 +211,7 @@ fn server() {
     assert_eq!(feed.entries().len(), 1);
     assert_eq!(
         feed.entries()
-            .last()
+            .first()
             .unwrap()
             .links()
             .first()
@@ -155,9 +220,82 @@ fn server() {
         url
     );
 
+    // Check the feed links to itself
+    let self_link = feed
+        .links()
+        .iter()
+        .find(|link| link.rel() == "self")
+        .expect("missing self link");
+    assert_eq!(
+        self_link.href(),
+        format!("http://{}/feed/{}", address, FEED_TOKEN)
+    );
+
+    // Check query parameters on the feed route are ignored
+    let res = minreq::get(format!("http://{}/feed/{}?x=1", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed with query failed");
+    assert_eq!(res.status_code, 200);
+    atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("feed with query is valid");
+
+    // Check that a percent-encoded path matches the feed route
+    let encoded_token = format!("%{:02X}{}", FEED_TOKEN.as_bytes()[0], &FEED_TOKEN[1..]);
+    let res = minreq::get(format!("http://{}/feed/{}", address, encoded_token))
+        .send()
+        .expect("GET /feed with encoded path failed");
+    assert_eq!(res.status_code, 200);
+    atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("feed with encoded path is valid");
+    let res = minreq::get(format!("http://{}/feed/%ZZ{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed with invalid escape failed");
+    assert_eq!(res.status_code, 404);
+
     // Check 304
     assert_eq!(fetch_feed_conditional(&last_modified, &address), 304);
 
+    // Check ETag
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    let etag = res.headers.get("etag").expect("ETag header is set").clone();
+    assert_eq!(
+        res.headers.get("cache-control").map(String::as_str),
+        Some("max-age=300")
+    );
+    let body_len = res.as_bytes().len();
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .with_header("If-None-Match", &etag)
+        .send()
+        .expect("GET /feed with If-None-Match failed");
+    assert_eq!(res.status_code, 304);
+    assert_eq!(res.headers.get("etag"), Some(&etag));
+    assert_eq!(
+        res.headers.get("cache-control").map(String::as_str),
+        Some("max-age=300")
+    );
+    assert_eq!(
+        res.headers.get("x-feedlynx-version").map(String::as_str),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .with_header("If-None-Match", "W/\"other\"")
+        .with_header("If-Modified-Since", &last_modified)
+        .send()
+        .expect("GET /feed with If-None-Match failed");
+    assert_eq!(res.status_code, 200);
+
+    // Check HEAD has the same headers as GET but no body
+    let res = minreq::head(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("HEAD /feed failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(res.headers.get("etag"), Some(&etag));
+    assert_eq!(
+        res.headers.get("content-length"),
+        Some(&body_len.to_string())
+    );
+    assert!(res.as_bytes().is_empty());
+
     // Check missing content type in POST is rejected
     let res = prepare_add_link(url, PRIVATE_TOKEN, &address)
         .send()
@@ -167,12 +305,23 @@ fn server() {
 
     // Check wrong content type in POST is rejected
     let res = prepare_add_link(url, PRIVATE_TOKEN, &address)
-        .with_header("Content-Type", "application/json")
+        .with_header("Content-Type", "text/plain")
         .send()
         .expect("POST /add with wrong content type failed");
     assert_eq!(res.status_code, 415);
     assert!(res.as_str().unwrap().contains("Unsupported media type"));
 
+    // Check that the content type is matched case-insensitively, ignoring whitespace. The
+    // link is already in the feed so it's accepted as a duplicate.
+    let res = prepare_add_link(url, PRIVATE_TOKEN, &address)
+        .with_header(
+            "Content-Type",
+            " Application/X-WWW-Form-Urlencoded ;Charset = \"US-ASCII\"",
+        )
+        .send()
+        .expect("POST /add with mixed case content type failed");
+    assert_eq!(res.status_code, 409);
+
     // Check unsupported charset in POST is rejected
     let res = prepare_add_link(url, PRIVATE_TOKEN, &address)
         .with_header(
@@ -187,6 +336,316 @@ fn server() {
     // Check that token is required to add link
     add_link_wrong_token(url, &address);
 
+    // Check that non-web URLs are rejected
+    let res = prepare_add_link("file:///etc/passwd", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add with file URL failed");
+    assert_eq!(res.status_code, 400);
+    assert!(res.as_str().unwrap().contains("Unsupported URL scheme"));
+
+    // Check that a missing url is distinguished from an invalid one
+    let body = form::Serializer::new(String::new())
+        .append_pair("token", PRIVATE_TOKEN)
+        .finish();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /add without url failed");
+    assert_eq!(res.status_code, 400);
+    assert!(res.as_str().unwrap().contains("Missing url"));
+
+    let res = prepare_add_link("", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add with empty url failed");
+    assert_eq!(res.status_code, 400);
+    assert!(res.as_str().unwrap().contains("Invalid URL"));
+
+    // Check that forwarded headers are ignored unless the proxy is trusted
+    let res = minreq::get(format!("http://{}/", address))
+        .with_header("X-Forwarded-Host", "feedlynx.example.com")
+        .with_header("X-Forwarded-Proto", "https")
+        .send()
+        .expect("GET / with forwarded headers failed");
+    let body = res.as_str().unwrap();
+    assert!(body.contains(&format!("http://{}/feed/", address)));
+
+    // Check the add form is pre-filled from the query string
+    let query = form::Serializer::new(String::new())
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("url", "https://example.com/?a=1&b=2")
+        .append_pair("title", "\"Quoted\" <title>")
+        .finish();
+    let res = minreq::get(format!("http://{}/add?{}", address, query))
+        .send()
+        .expect("GET /add failed");
+    assert_eq!(res.status_code, 200);
+    let body = res.as_str().unwrap();
+    assert!(body.contains(r#"value="https://example.com/?a=1&amp;b=2""#));
+    assert!(body.contains(r#"value="&quot;Quoted&quot; &lt;title&gt;""#));
+
+    // Check the add form requires the token
+    let res = minreq::get(format!("http://{}/add?token=nope-token", address))
+        .send()
+        .expect("GET /add with wrong token failed");
+    assert_eq!(res.status_code, 401);
+
+    // Check that the id and URL of the new entry are returned
+    let res = prepare_add_link("http://example.com/json", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_header("Accept", "application/json")
+        .send()
+        .expect("POST /add accepting JSON failed");
+    assert_eq!(res.status_code, 201);
+    let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    let obj: &HashMap<_, _> = json.get().unwrap();
+    assert_eq!(obj["status"].get::<String>().unwrap(), "added");
+    assert_eq!(
+        obj["url"].get::<String>().unwrap(),
+        "http://example.com/json"
+    );
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(
+        feed.entries().first().unwrap().id(),
+        obj["id"].get::<String>().unwrap()
+    );
+
+    // Errors are JSON too
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_header("Accept", "application/json")
+        .with_body("a".repeat(1024 * 1024 + 1))
+        .send()
+        .expect("POST /add with large body failed");
+    assert_eq!(res.status_code, 413);
+    assert_eq!(
+        res.headers.get("content-type").map(String::as_str),
+        Some("application/json")
+    );
+    let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    assert_eq!(json["status"], JsonValue::from("error".to_string()));
+    assert_eq!(
+        json["message"],
+        JsonValue::from("POST body exceeded maximum size".to_string())
+    );
+    let res = prepare_add_link("http://example.com/json", "nope-token", &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_header("Accept", "application/json")
+        .send()
+        .expect("POST /add with wrong token failed");
+    assert_eq!(res.status_code, 401);
+    let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    assert_eq!(json["status"], JsonValue::from("error".to_string()));
+
+    let res = prepare_add_link("http://example.com/text", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add failed");
+    assert_eq!(res.status_code, 201);
+    let body = res.as_str().unwrap();
+    assert!(body.starts_with("Added\nid: tag:"), "{body}");
+    assert!(body.ends_with("url: http://example.com/text\n"), "{body}");
+
+    // Check that adding a duplicate link is rejected
+    let res = prepare_add_link("http://example.com/text", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add with duplicate failed");
+    assert_eq!(res.status_code, 409);
+    assert!(res.as_str().unwrap().contains("Duplicate"));
+
+    // Check that rejecting a duplicate doesn't modify the feed. The sleep ensures the mtime
+    // would be different if the file was rewritten.
+    let (_, last_modified) = fetch_feed(&address);
+    std::thread::sleep(Duration::from_millis(1100));
+    let res = prepare_add_link("http://example.com/text", PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add with duplicate failed");
+    assert_eq!(res.status_code, 409);
+    let (_, last_modified_after) = fetch_feed(&address);
+    assert_eq!(last_modified, last_modified_after);
+
+    // Check that tags are added as categories
+    let body = form::Serializer::new(String::new())
+        .append_pair("url", "http://example.com/tagged")
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("tags", "rust, later,")
+        .finish();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /add with tags failed");
+    assert_eq!(res.status_code, 201);
+    let (feed, _) = fetch_feed(&address);
+    let terms = feed
+        .entries()
+        .first()
+        .unwrap()
+        .categories()
+        .iter()
+        .filter(|category| category.scheme().is_none())
+        .map(|category| category.term())
+        .collect::<Vec<_>>();
+    assert_eq!(terms, ["rust", "later"]);
+
+    // Check that entries can be marked read and unread
+    let id = feed.entries().first().unwrap().id().to_string();
+    let mark = |id: &str, read: &str| {
+        let body = form::Serializer::new(String::new())
+            .append_pair("token", PRIVATE_TOKEN)
+            .append_pair("id", id)
+            .append_pair("read", read)
+            .finish();
+        minreq::post(format!("http://{}/mark", address))
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_body(body)
+            .send()
+            .expect("POST /mark failed")
+    };
+    let terms = || {
+        let (feed, _) = fetch_feed(&address);
+        feed.entries()
+            .first()
+            .unwrap()
+            .categories()
+            .iter()
+            .filter(|category| matches!(category.scheme(), None | Some("feedlynx:read")))
+            .map(|category| category.term().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(mark(&id, "true").status_code, 200);
+    assert_eq!(terms(), ["rust", "later", "read"]);
+    assert_eq!(mark(&id, "false").status_code, 200);
+    assert_eq!(terms(), ["rust", "later"]);
+    assert_eq!(mark("tag:unknown", "true").status_code, 404);
+    assert_eq!(mark(&id, "maybe").status_code, 400);
+
+    // Check that the source is recorded and counted in /info
+    let body = form::Serializer::new(String::new())
+        .append_pair("url", "http://example.com/sourced")
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("source", "share-sheet")
+        .finish();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /add with source failed");
+    assert_eq!(res.status_code, 201);
+    let (feed, _) = fetch_feed(&address);
+    let source = feed
+        .entries()
+        .first()
+        .unwrap()
+        .categories()
+        .iter()
+        .find(|category| category.scheme() == Some("feedlynx:source"))
+        .map(|category| category.term());
+    assert_eq!(source, Some("share-sheet"));
+    let info = get_info(None, &address);
+    let obj: &HashMap<_, _> = info.get().unwrap();
+    let sources: &HashMap<_, _> = obj["sources"].get().unwrap();
+    assert_eq!(sources["share-sheet"].get::<f64>(), Some(&1.0));
+    let updated: &String = obj["updated"].get().unwrap();
+    assert_eq!(
+        chrono::DateTime::parse_from_rfc3339(updated).ok().as_ref(),
+        Some(feed.updated())
+    );
+    assert!(!obj.contains_key("recent"));
+
+    // Check that the most recent entries are included when a limit is given
+    let body = form::Serializer::new(String::new())
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("limit", "2")
+        .finish();
+    let res = minreq::post(format!("http://{}/info", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /info with limit failed");
+    assert_eq!(res.status_code, 200);
+    let info: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    let recent: &Vec<_> = info["recent"].get().expect("recent is not an array");
+    assert_eq!(recent.len(), 2);
+    let newest = feed.entries().first().unwrap();
+    let newest_url: &String = recent[0]["url"].get().unwrap();
+    assert_eq!(newest_url, newest.links()[0].href());
+    let newest_id: &String = recent[0]["id"].get().unwrap();
+    assert_eq!(newest_id, newest.id());
+    let newest_added: &String = recent[0]["added"].get().unwrap();
+    assert!(DateTime::parse_from_rfc3339(newest_added).is_ok());
+    let body = form::Serializer::new(String::new())
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("limit", "many")
+        .finish();
+    let res = minreq::post(format!("http://{}/info", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /info with limit failed");
+    assert_eq!(res.status_code, 400);
+
+    // Check that title_override uses the supplied title as is
+    let body = form::Serializer::new(String::new())
+        .append_pair("url", "http://example.com/override")
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("title", "Short")
+        .append_pair("title_override", "true")
+        .finish();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /add with title_override failed");
+    assert_eq!(res.status_code, 201);
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries().first().unwrap().title().as_str(), "Short");
+
+    // Check that links can be added with a GET request
+    let query = form::Serializer::new(String::new())
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("url", "http://example.com/shared")
+        .append_pair("title", "Shared")
+        .append_pair("submit", "true")
+        .finish();
+    let res = minreq::get(format!("http://{}/add?{}", address, query))
+        .send()
+        .expect("GET /add with submit failed");
+    assert_eq!(res.status_code, 201);
+    let (feed, _) = fetch_feed(&address);
+    let entry = feed.entries().first().unwrap();
+    assert_eq!(entry.links()[0].href(), "http://example.com/shared");
+    assert_eq!(entry.title().as_str(), "Shared");
+    let res = minreq::get(format!(
+        "http://{}/add?token=nope-token&url=http://example.com/nope&submit=true",
+        address
+    ))
+    .send()
+    .expect("GET /add with submit and wrong token failed");
+    assert_eq!(res.status_code, 401);
+
+    // Check that known paths reject other methods
+    let res = minreq::get(format!("http://{}/info", address))
+        .send()
+        .expect("GET /info failed");
+    assert_eq!(res.status_code, 405);
+    assert_eq!(
+        res.headers.get("allow").map(String::as_str),
+        Some("POST, OPTIONS")
+    );
+    let res = minreq::delete(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("DELETE /feed failed");
+    assert_eq!(res.status_code, 405);
+    assert_eq!(
+        res.headers.get("allow").map(String::as_str),
+        Some("GET, HEAD")
+    );
+
     // Check that token is required to fetch feed
     let res = minreq::get(format!("http://{}/feed/{}", address, "invalid-token"))
         .send()
@@ -267,7 +726,7 @@ fn trim() {
         feed.entries()
             .iter()
             .find(|entry| entry.id() == id)
-            .expect(&format!("expected to find entry with id: {}", id));
+            .unwrap_or_else(|| panic!("expected to find entry with id: {}", id));
     });
 
     // Add a link to the feed, which should trigger trimming, check that the trim worked.
@@ -286,6 +745,1488 @@ fn trim() {
     assert!(removed);
 }
 
+#[test]
+fn trust_proxy() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 2;
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_TRUST_PROXY", "1")]);
+    let address = format!("127.0.0.1:{}", port);
+
+    let res = minreq::get(format!("http://{}/", address))
+        .with_header("X-Forwarded-Host", "feedlynx.example.com, proxy.internal")
+        .with_header("X-Forwarded-Proto", "https")
+        .send()
+        .expect("GET / failed");
+    assert_eq!(res.status_code, 200);
+    let body = res.as_str().unwrap();
+    assert!(
+        body.contains("https://feedlynx.example.com/feed/"),
+        "{body}"
+    );
+
+    // The forwarded host is escaped in the index page
+    let res = minreq::get(format!("http://{}/", address))
+        .with_header("X-Forwarded-Host", "<b>example.com</b>")
+        .send()
+        .expect("GET / failed");
+    assert_eq!(res.status_code, 200);
+    let body = res.as_str().unwrap();
+    assert!(!body.contains("<b>example.com</b>"), "{body}");
+    assert!(
+        body.contains("http://&lt;b&gt;example.com&lt;/b&gt;/add"),
+        "{body}"
+    );
+}
+
+#[test]
+fn cors_origin() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 3;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[(
+            "FEEDLYNX_CORS_ORIGIN",
+            "https://one.example.com, moz-extension://abcd",
+        )],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    // An allowed origin is echoed back
+    let res = prepare_get_info(PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_header("Origin", "moz-extension://abcd")
+        .send()
+        .expect("POST /info failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(
+        res.headers
+            .get("access-control-allow-origin")
+            .map(String::as_str),
+        Some("moz-extension://abcd")
+    );
+    assert_eq!(res.headers.get("vary").map(String::as_str), Some("Origin"));
+
+    // Other origins are not allowed
+    let res = prepare_get_info(PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_header("Origin", "https://evil.example.com")
+        .send()
+        .expect("POST /info failed");
+    assert_eq!(res.status_code, 200);
+    assert!(!res.headers.contains_key("access-control-allow-origin"));
+}
+
+#[test]
+fn feed_id() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 4;
+    let feed_id = "tag:feedlynx.example.com,2024:test";
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_FEED_ID", feed_id)]);
+
+    let (feed, _) = fetch_feed(&format!("127.0.0.1:{}", port));
+    assert_eq!(feed.id(), feed_id);
+}
+
+#[test]
+fn gzip_feed() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml.gz")));
+    let port = PORT + 5;
+    let _child = start_server(feed_path.path(), port, &[]);
+    let address = format!("127.0.0.1:{}", port);
+
+    // The feed is stored compressed
+    let data = fs::read(feed_path.path()).expect("unable to read feed");
+    assert!(data.starts_with(&[0x1f, 0x8b]));
+
+    add_link("http://example.com/gzip", &address);
+    let data = fs::read(feed_path.path()).expect("unable to read feed");
+    assert!(data.starts_with(&[0x1f, 0x8b]));
+
+    // Clients that don't accept gzip get the decompressed feed
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries().len(), 1);
+
+    // Clients that accept gzip get it as is
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .with_header("Accept-Encoding", "gzip, deflate")
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(
+        res.headers.get("content-encoding").map(String::as_str),
+        Some("gzip")
+    );
+    assert_eq!(res.as_bytes(), data);
+
+    // HEAD reports the length of the decompressed feed to clients that don't accept gzip
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    let decompressed_len = res.as_bytes().len();
+    let res = minreq::head(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("HEAD /feed failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(
+        res.headers.get("content-length"),
+        Some(&decompressed_len.to_string())
+    );
+}
+
+#[test]
+fn named_feeds() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let watch_path = RmOnDrop::new(std::env::temp_dir().join(format!("watch.{rand}.xml")));
+    let feeds = format!("watch={}", watch_path.path().display());
+    let port = PORT + 6;
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_FEEDS", &feeds)]);
+    let address = format!("127.0.0.1:{}", port);
+
+    // The named feed is created on startup
+    assert!(watch_path.path().exists());
+
+    // The feeds are listed with their URLs
+    let res = minreq::get(format!("http://{}/feeds", address))
+        .send()
+        .expect("GET /feeds failed");
+    assert_eq!(res.status_code, 401);
+    let res = minreq::get(format!("http://{}/feeds?token={}", address, PRIVATE_TOKEN))
+        .send()
+        .expect("GET /feeds failed");
+    assert_eq!(res.status_code, 200);
+    let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    let obj: &HashMap<_, _> = json.get().unwrap();
+    assert_eq!(obj["status"].get::<String>().unwrap(), "ok");
+    let feeds: &Vec<_> = obj["feeds"].get().unwrap();
+    let feeds = feeds
+        .iter()
+        .map(|feed| {
+            let feed: &HashMap<_, _> = feed.get().unwrap();
+            (
+                feed["name"].get::<String>().unwrap().as_str(),
+                feed["url"].get::<String>().unwrap().as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let default_url = format!("http://{}/feed/{}", address, FEED_TOKEN);
+    let watch_url = format!("http://{}/feed/{}/watch", address, FEED_TOKEN);
+    assert_eq!(
+        feeds,
+        [
+            ("default", default_url.as_str()),
+            ("watch", watch_url.as_str())
+        ]
+    );
+
+    // Links are added to the selected feed
+    let add = |feed: &str| {
+        let body = form::Serializer::new(String::new())
+            .append_pair("url", "http://example.com/watch")
+            .append_pair("token", PRIVATE_TOKEN)
+            .append_pair("feed", feed)
+            .finish();
+        minreq::post(format!("http://{}/add", address))
+            .with_body(body)
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .send()
+            .expect("POST /add failed")
+    };
+    assert_eq!(add("watch").status_code, 201);
+    assert_eq!(add("nope").status_code, 400);
+
+    let (feed, _) = fetch_feed(&address);
+    assert!(feed.entries().is_empty());
+    let res = minreq::get(&watch_url).send().expect("GET /feed failed");
+    assert_eq!(res.status_code, 200);
+    let feed = atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+    assert_eq!(feed.entries().len(), 1);
+
+    // Unknown feeds are not found
+    let res = minreq::get(format!("http://{}/feed/{}/nope", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 404);
+}
+
+#[test]
+fn read_timeout() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 7;
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_READ_TIMEOUT", "1")]);
+
+    // Send the body slowly so that it takes longer than the timeout. Small bodies are read by
+    // tiny_http before the request is handled so this needs to claim to send a larger one.
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("unable to connect");
+    write!(
+        stream,
+        "POST /add HTTP/1.1\r\nHost: 127.0.0.1\r\n\
+         Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 100000\r\n\r\n"
+    )
+    .unwrap();
+    for _ in 0..4 {
+        stream.write_all(b"token").unwrap();
+        std::thread::sleep(Duration::from_millis(400));
+    }
+
+    let mut response = [0; 1024];
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let len = stream.read(&mut response).expect("no response");
+    let response = String::from_utf8_lossy(&response[..len]);
+    assert!(response.starts_with("HTTP/1.1 408"), "response: {response}");
+}
+
+#[test]
+fn read_timeout_no_body() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 29;
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_READ_TIMEOUT", "1")]);
+
+    // Claim to send a body but don't send any of it, on as many connections as the server reads
+    // bodies for at once. That's more than there are threads handling requests, each handler
+    // waits for the timeout before giving up on one.
+    let streams = (0..16)
+        .map(|_| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("unable to connect");
+            write!(
+                stream,
+                "POST /add HTTP/1.1\r\nHost: 127.0.0.1\r\n\
+                 Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 100000\r\n\r\n"
+            )
+            .unwrap();
+            // Give tiny_http time to start a thread for the connection
+            std::thread::sleep(Duration::from_millis(100));
+            stream
+        })
+        .collect::<Vec<_>>();
+    // Wait for the handlers to give up on all of them, four at a time
+    std::thread::sleep(Duration::from_millis(4500));
+
+    // Other requests are still handled
+    let res = minreq::get(format!("http://127.0.0.1:{port}/version"))
+        .with_timeout(5)
+        .send()
+        .expect("GET /version failed");
+    assert_eq!(res.status_code, 200);
+
+    // The stalled requests still hold the threads reading their body so no more are read
+    let res = minreq::post(format!("http://127.0.0.1:{port}/add"))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body("url=http://example.com/")
+        .with_timeout(5)
+        .send()
+        .expect("POST /add failed");
+    assert_eq!(res.status_code, 503);
+
+    // The timed out requests are responded to when the client finishes sending
+    for mut stream in streams {
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).expect("no response");
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 408"), "response: {response}");
+    }
+}
+
+#[test]
+fn async_fetch() {
+    let page_port = serve_page("<html><head><title>Fetched Title</title></head></html>");
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 8;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[
+            ("FEEDLYNX_ASYNC_FETCH", "1"),
+            ("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1"),
+        ],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    // The link is added before the page is fetched
+    let res = prepare_add_link(
+        &format!("http://127.0.0.1:{page_port}/"),
+        PRIVATE_TOKEN,
+        &address,
+    )
+    .with_header("Content-Type", "application/x-www-form-urlencoded")
+    .send()
+    .expect("POST /add failed");
+    assert_eq!(res.status_code, 202);
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries().len(), 1);
+
+    // Then updated once it has been fetched
+    let mut title = String::new();
+    for _ in 0..50 {
+        let (feed, _) = fetch_feed(&address);
+        title = feed.entries()[0].title().to_string();
+        if title == "Fetched Title" {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(title, "Fetched Title");
+}
+
+#[test]
+fn allowed_domains() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 25;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[
+            ("FEEDLYNX_ALLOWED_DOMAINS", "example.com, youtube.com"),
+            ("FEEDLYNX_BLOCKED_DOMAINS", "spam.example.com"),
+        ],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    add_link("http://example.com/allowed", &address);
+    add_link("http://www.Example.com/subdomain", &address);
+
+    // The blocked domain takes precedence over the allowed parent domain
+    for url in [
+        "http://example.org/",
+        "http://notexample.com/",
+        "http://spam.example.com/",
+        "http://www.spam.example.com/",
+    ] {
+        let res = prepare_add_link(url, PRIVATE_TOKEN, &address)
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .send()
+            .expect("POST /add failed");
+        assert_eq!(res.status_code, 403, "{url}");
+        assert_eq!(res.as_str().unwrap(), "Failed: Domain not allowed\n");
+    }
+
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries().len(), 2);
+}
+
+#[test]
+fn selftest() {
+    let page_port = serve_page("<html><head><title>Self-test</title></head></html>");
+    // Nothing is listening on this port once the listener is dropped
+    let closed_port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("unable to bind")
+        .port();
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 24;
+
+    // Strict mode refuses to start if the page can't be fetched
+    let closed_url = format!("http://127.0.0.1:{closed_port}/");
+    let output = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &port.to_string()),
+            ("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1"),
+            ("FEEDLYNX_SELFTEST", "1"),
+            ("FEEDLYNX_SELFTEST_URL", &closed_url),
+            ("FEEDLYNX_SELFTEST_STRICT", "1"),
+        ])
+        .arg(feed_path.path())
+        .output()
+        .expect("failed to run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Self-test failed"), "stderr: {stderr}");
+
+    // Otherwise it starts, without adding anything to the feed
+    let page_url = format!("http://127.0.0.1:{page_port}/");
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[
+            ("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1"),
+            ("FEEDLYNX_SELFTEST", "1"),
+            ("FEEDLYNX_SELFTEST_URL", &page_url),
+        ],
+    );
+    let (feed, _) = fetch_feed(&format!("127.0.0.1:{}", port));
+    assert!(feed.entries().is_empty());
+}
+
+#[test]
+fn metadata_fetched() {
+    let page_port = serve_page("<html><head><title>Fetched</title></head></html>");
+    // Nothing is listening on this port once the listener is dropped
+    let closed_port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("unable to bind")
+        .port();
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 21;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1")],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    let add = |url: String, entry_count: &str| {
+        let res = prepare_add_link(&url, PRIVATE_TOKEN, &address)
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_header("Accept", "application/json")
+            .send()
+            .expect("POST /add failed");
+        // The link is added either way
+        assert_eq!(res.status_code, 201);
+        assert_eq!(
+            res.headers
+                .get("x-feedlynx-entry-count")
+                .map(String::as_str),
+            Some(entry_count)
+        );
+        let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+        json
+    };
+
+    let json = add(format!("http://127.0.0.1:{page_port}/"), "1");
+    assert_eq!(json["metadata_fetched"], JsonValue::Boolean(true));
+    let obj: &HashMap<_, _> = json.get().unwrap();
+    assert!(!obj.contains_key("fetch_error"));
+
+    let json = add(format!("http://127.0.0.1:{closed_port}/"), "2");
+    assert_eq!(json["metadata_fetched"], JsonValue::Boolean(false));
+    assert!(json["fetch_error"].is_string());
+}
+
+#[test]
+fn blank_fetched_title() {
+    let page_port = serve_page("<html><head><title>\n    \n</title></head></html>");
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 19;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1")],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    // The whitespace title is longer than the submitted one but is ignored
+    let body = form::Serializer::new(String::new())
+        .append_pair("url", &format!("http://127.0.0.1:{page_port}/"))
+        .append_pair("token", PRIVATE_TOKEN)
+        .append_pair("title", "Hi")
+        .finish();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body)
+        .send()
+        .expect("POST /add failed");
+    assert_eq!(res.status_code, 201);
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries()[0].title().as_str(), "Hi");
+}
+
+#[test]
+fn concurrent_adds() {
+    let page_port = serve_page("<html><head><title>Page</title></head></html>");
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 9;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1")],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    // None of the adds may overwrite the others
+    let adds = ["a", "b", "c", "d"].map(|path| {
+        let url = format!("http://127.0.0.1:{page_port}/{path}");
+        let address = address.clone();
+        std::thread::spawn(move || add_link(&url, &address))
+    });
+    adds.into_iter()
+        .for_each(|add| add.join().expect("add panicked"));
+
+    let (feed, _) = fetch_feed(&address);
+    let mut hrefs = feed
+        .entries()
+        .iter()
+        .map(|entry| entry.links()[0].href().to_string())
+        .collect::<Vec<_>>();
+    hrefs.sort();
+    assert_eq!(
+        hrefs,
+        ["a", "b", "c", "d"].map(|path| format!("http://127.0.0.1:{page_port}/{path}"))
+    );
+}
+
+#[test]
+fn base_path() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 10;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[("FEEDLYNX_BASE_PATH", "/feedlynx/")],
+    );
+    let address = format!("127.0.0.1:{}", port);
+
+    // The index is served at the base path, with or without a trailing slash
+    for path in ["/feedlynx", "/feedlynx/"] {
+        let res = minreq::get(format!("http://{}{}", address, path))
+            .send()
+            .expect("GET index failed");
+        assert_eq!(res.status_code, 200);
+        let body = res.as_str().unwrap();
+        assert!(body.contains(&format!("http://{}/feedlynx/feed/", address)));
+        assert!(body.contains(&format!("http://{}/feedlynx/add", address)));
+    }
+
+    // Routes outside the base path are not found
+    for path in ["/", "/add", "/feedlynxadd", &format!("/feed/{FEED_TOKEN}")] {
+        let res = minreq::get(format!("http://{}{}", address, path))
+            .send()
+            .expect("GET outside base path failed");
+        assert_eq!(res.status_code, 404, "{path}");
+    }
+
+    // The add form posts to the prefixed route
+    let res = minreq::get(format!(
+        "http://{}/feedlynx/add?token={}",
+        address, PRIVATE_TOKEN
+    ))
+    .send()
+    .expect("GET /add failed");
+    assert!(res.as_str().unwrap().contains(r#"action="/feedlynx/add""#));
+
+    // Links are added and the feed is served under the base path
+    let res = prepare_add_link(
+        "http://example.com/",
+        PRIVATE_TOKEN,
+        &format!("{address}/feedlynx"),
+    )
+    .with_header("Content-Type", "application/x-www-form-urlencoded")
+    .send()
+    .expect("POST /add failed");
+    assert_eq!(res.status_code, 201);
+    let res = minreq::get(format!("http://{}/feedlynx/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 200);
+    let feed = atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+    assert_eq!(feed.entries().len(), 1);
+    let self_link = feed.links().iter().find(|link| link.rel() == "self");
+    assert_eq!(
+        self_link.map(|link| link.href()),
+        Some(format!("http://{}/feedlynx/feed/{}", address, FEED_TOKEN).as_str())
+    );
+}
+
+#[test]
+fn metrics() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 11;
+    let _child = start_server(feed_path.path(), port, &[]);
+    let address = format!("127.0.0.1:{}", port);
+
+    let res = minreq::get(format!("http://{}/metrics", address))
+        .send()
+        .expect("GET /metrics failed");
+    assert_eq!(res.status_code, 401);
+
+    // The page is on a private host so it isn't fetched, but the link is still added
+    let page_port = serve_page("<html><head><title>Metrics</title></head></html>");
+    let url = format!("http://127.0.0.1:{page_port}/");
+    add_link(&url, &address);
+    let res = prepare_add_link(&url, PRIVATE_TOKEN, &address)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .send()
+        .expect("POST /add failed");
+    assert_eq!(res.status_code, 409);
+
+    let res = minreq::get(format!(
+        "http://{}/metrics?token={}",
+        address, PRIVATE_TOKEN
+    ))
+    .send()
+    .expect("GET /metrics failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(
+        res.headers.get("content-type").map(String::as_str),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    let body = res.as_str().unwrap();
+    assert!(
+        body.contains("# TYPE feedlynx_adds_total counter\n"),
+        "{body}"
+    );
+    assert!(body.contains("\nfeedlynx_adds_total 1\n"), "{body}");
+    assert!(body.contains("\nfeedlynx_add_errors_total 1\n"), "{body}");
+    assert!(
+        body.contains("\nfeedlynx_fetch_failures_total 1\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("\nfeedlynx_feed_entries{feed=\"default\"} 1\n"),
+        "{body}"
+    );
+}
+
+#[test]
+fn cache_max_age_disabled() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 20;
+    let _child = start_server(feed_path.path(), port, &[("FEEDLYNX_CACHE_MAX_AGE", "0")]);
+
+    let res = minreq::get(format!("http://127.0.0.1:{}/feed/{}", port, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 200);
+    assert_eq!(res.headers.get("cache-control"), None);
+}
+
+#[test]
+fn clear() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+
+    add_link("http://example.com/one", &address);
+    add_link("http://example.com/two", &address);
+    let (feed, _) = fetch_feed(&address);
+    let id = feed.id().to_string();
+
+    let clear = |token: &str| {
+        let body = form::Serializer::new(String::new())
+            .append_pair("token", token)
+            .finish();
+        minreq::post(format!("http://{}/clear", address))
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_body(body)
+            .send()
+            .expect("POST /clear failed")
+    };
+    assert_eq!(clear("nope-token").status_code, 401);
+    let res = clear(PRIVATE_TOKEN);
+    assert_eq!(res.status_code, 200);
+    assert_eq!(res.as_str().unwrap(), "Cleared\nremoved: 2\n");
+
+    // The feed keeps its id
+    let (feed, _) = fetch_feed(&address);
+    assert!(feed.entries().is_empty());
+    assert_eq!(feed.id(), id);
+}
+
+#[test]
+fn feed_unavailable() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+
+    // Simulate a request arriving while the feed is being replaced
+    fs::remove_file(feed_path.path()).expect("unable to remove feed");
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 503);
+    assert_eq!(
+        res.headers.get("retry-after").map(String::as_str),
+        Some("1")
+    );
+    assert_eq!(
+        res.headers.get("content-type").map(String::as_str),
+        Some("text/plain; charset=utf-8")
+    );
+}
+
+#[test]
+fn feed_error_page() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+
+    // Filtering the feed requires parsing it, which fails
+    fs::write(feed_path.path(), "<feed").expect("unable to corrupt feed");
+    let res = minreq::get(format!("http://{}/feed/{}?tag=rust", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    assert_eq!(res.status_code, 500);
+    assert_eq!(
+        res.headers.get("content-type").map(String::as_str),
+        Some("text/html; charset=utf-8")
+    );
+}
+
+#[test]
+fn tag_filter() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+
+    for (url, tags) in [
+        ("http://example.com/rust", "rust"),
+        ("http://example.com/video", "video, later"),
+        ("http://example.com/untagged", ""),
+    ] {
+        let body = form::Serializer::new(String::new())
+            .append_pair("url", url)
+            .append_pair("token", PRIVATE_TOKEN)
+            .append_pair("tags", tags)
+            .finish();
+        let res = minreq::post(format!("http://{}/add", address))
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_body(body)
+            .send()
+            .expect("POST /add with tags failed");
+        assert_eq!(res.status_code, 201);
+    }
+
+    let filtered = |tag: &str| {
+        let res = minreq::get(format!(
+            "http://{}/feed/{}?tag={}",
+            address, FEED_TOKEN, tag
+        ))
+        .send()
+        .expect("GET /feed?tag failed");
+        assert_eq!(res.status_code, 200);
+        assert_eq!(
+            res.headers.get("content-type").map(String::as_str),
+            Some("application/atom+xml; charset=utf-8")
+        );
+        let feed =
+            atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+        feed.entries()
+            .iter()
+            .map(|entry| entry.links()[0].href().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(filtered("rust"), ["http://example.com/rust"]);
+    assert_eq!(filtered("later"), ["http://example.com/video"]);
+    assert!(filtered("missing").is_empty());
+
+    // Without a tag the whole feed is served
+    let (feed, _) = fetch_feed(&address);
+    assert_eq!(feed.entries().len(), 3);
+    let res = minreq::get(format!("http://{}/feed/{}?tag=", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed?tag= failed");
+    let feed = atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+    assert_eq!(feed.entries().len(), 3);
+}
+
+#[test]
+fn since_filter() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+
+    add_link("http://example.com/one", &address);
+    // Make sure the entries have distinct updated times
+    std::thread::sleep(Duration::from_millis(10));
+    add_link("http://example.com/two", &address);
+    let (feed, _) = fetch_feed(&address);
+    let since = feed.entries().last().unwrap().updated().to_rfc3339();
+
+    let filtered = |since: &str| {
+        let query = form::Serializer::new(String::new())
+            .append_pair("since", since)
+            .finish();
+        let res = minreq::get(format!("http://{}/feed/{}?{}", address, FEED_TOKEN, query))
+            .send()
+            .expect("GET /feed?since failed");
+        assert_eq!(res.status_code, 200);
+        let feed =
+            atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+        feed.entries()
+            .iter()
+            .map(|entry| entry.links()[0].href().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(filtered(&since), ["http://example.com/two"]);
+    // A + in the offset that isn't percent-encoded is decoded as a space
+    let res = minreq::get(format!(
+        "http://{}/feed/{}?since={}",
+        address, FEED_TOKEN, since
+    ))
+    .send()
+    .expect("GET /feed?since failed");
+    let feed = atom::Feed::read_from(Cursor::new(res.as_bytes())).expect("failed to parse feed");
+    assert_eq!(feed.entries().len(), 1);
+    assert!(filtered("2999-01-01T00:00:00Z").is_empty());
+    // An unparsable time serves the full feed
+    assert_eq!(filtered("yesterday").len(), 2);
+}
+
+#[test]
+fn entry_order() {
+    let urls = |address: &str| {
+        let (feed, _) = fetch_feed(address);
+        feed.entries()
+            .iter()
+            .map(|entry| entry.links()[0].href().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Newest first by default
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let (_server, address) = spawn_server(feed_path.path());
+    add_link("http://example.com/one", &address);
+    add_link("http://example.com/two", &address);
+    assert_eq!(
+        urls(&address),
+        ["http://example.com/two", "http://example.com/one"]
+    );
+
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 26;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[("FEEDLYNX_ENTRY_ORDER", "oldest")],
+    );
+    let address = format!("127.0.0.1:{}", port);
+    add_link("http://example.com/one", &address);
+    add_link("http://example.com/two", &address);
+    assert_eq!(
+        urls(&address),
+        ["http://example.com/one", "http://example.com/two"]
+    );
+}
+
+#[test]
+fn multipart_add() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 13;
+    let _child = start_server(feed_path.path(), port, &[]);
+    let address = format!("127.0.0.1:{}", port);
+
+    let boundary = "----FeedlynxBoundary1234";
+    let part = |name: &str, value: &str| {
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+    };
+    let body = [
+        part("token", PRIVATE_TOKEN),
+        part("url", "http://example.com/multipart"),
+        part("title", "Shared with multipart"),
+        part("tags", "rust, later"),
+        format!("--{boundary}--\r\n"),
+    ]
+    .concat();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .with_body(body)
+        .send()
+        .expect("POST /add multipart failed");
+    assert_eq!(res.status_code, 201, "{}", res.as_str().unwrap());
+
+    let (feed, _) = fetch_feed(&address);
+    let entry = feed.entries().first().expect("entry was added");
+    assert_eq!(entry.links()[0].href(), "http://example.com/multipart");
+    assert_eq!(entry.title().as_str(), "Shared with multipart");
+    let terms = entry
+        .categories()
+        .iter()
+        .filter(|category| category.scheme().is_none())
+        .map(|category| category.term())
+        .collect::<Vec<_>>();
+    assert_eq!(terms, ["rust", "later"]);
+
+    // The token is still checked, and malformed bodies are rejected
+    let body = [part("token", "wrong"), format!("--{boundary}--\r\n")].concat();
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .with_body(body)
+        .send()
+        .expect("POST /add multipart failed");
+    assert_eq!(res.status_code, 401);
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "multipart/form-data; boundary=other")
+        .with_body(part("token", PRIVATE_TOKEN))
+        .send()
+        .expect("POST /add multipart failed");
+    assert_eq!(res.status_code, 400);
+    assert!(res.as_str().unwrap().contains("Invalid multipart body"));
+}
+
+#[test]
+fn json_bodies() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 14;
+    let _child = start_server(feed_path.path(), port, &[]);
+    let address = format!("127.0.0.1:{}", port);
+
+    let body = format!(
+        r#"{{"token": "{PRIVATE_TOKEN}", "url": "http://example.com/json", "title": "Added with JSON", "title_override": true, "tags": ["rust", "later"]}}"#
+    );
+    let res = minreq::post(format!("http://{}/add", address))
+        .with_header("Content-Type", "application/json")
+        .with_header("Accept", "application/json")
+        .with_body(body)
+        .send()
+        .expect("POST /add JSON failed");
+    assert_eq!(res.status_code, 201, "{}", res.as_str().unwrap());
+
+    let (feed, _) = fetch_feed(&address);
+    let entry = feed.entries().first().expect("entry was added");
+    assert_eq!(entry.links()[0].href(), "http://example.com/json");
+    assert_eq!(entry.title().as_str(), "Added with JSON");
+    let terms = entry
+        .categories()
+        .iter()
+        .filter(|category| category.scheme().is_none())
+        .map(|category| category.term())
+        .collect::<Vec<_>>();
+    assert_eq!(terms, ["rust", "later"]);
+
+    // Invalid JSON and non-object bodies are rejected
+    for body in ["{", r#"["token"]"#] {
+        let res = minreq::post(format!("http://{}/add", address))
+            .with_header("Content-Type", "application/json")
+            .with_body(body)
+            .send()
+            .expect("POST /add JSON failed");
+        assert_eq!(res.status_code, 400);
+        assert!(res.as_str().unwrap().contains("Invalid JSON body"));
+    }
+
+    let res = minreq::post(format!("http://{}/info", address))
+        .with_header("Content-Type", "application/json; charset=utf-8")
+        .with_body(format!(r#"{{"token": "{PRIVATE_TOKEN}"}}"#))
+        .send()
+        .expect("POST /info JSON failed");
+    assert_eq!(res.status_code, 200);
+    let json: JsonValue = res.as_str().unwrap().parse().expect("invalid JSON");
+    let status: &String = json["status"].get().expect("status is not a string");
+    assert_eq!(status, "ok");
+
+    let res = minreq::post(format!("http://{}/info", address))
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"token": "wrong"}"#)
+        .send()
+        .expect("POST /info JSON failed");
+    assert_eq!(res.status_code, 401);
+}
+
+#[test]
+fn external_feed_change() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 15;
+    let _child = start_server(feed_path.path(), port, &[]);
+    let address = format!("127.0.0.1:{}", port);
+
+    let (_, last_modified) = fetch_feed(&address);
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .send()
+        .expect("GET /feed failed");
+    let etag = res.headers.get("etag").expect("ETag header is set").clone();
+    assert_eq!(fetch_feed_conditional(&last_modified, &address), 304);
+
+    // Last-Modified has one second resolution
+    std::thread::sleep(Duration::from_millis(1100));
+
+    // Rewrite the feed as another process, or someone editing it by hand, would
+    let mut feed = feedlynx::Feed::read(feed_path.path()).expect("unable to read feed");
+    feed.set_id("urn:example:edited");
+    feed.save(&Default::default()).expect("unable to save feed");
+
+    let (feed, last_modified_after) = fetch_feed(&address);
+    assert_eq!(feed.id(), "urn:example:edited");
+    assert_ne!(last_modified, last_modified_after);
+    assert_eq!(fetch_feed_conditional(&last_modified, &address), 200);
+    let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
+        .with_header("If-None-Match", &etag)
+        .send()
+        .expect("GET /feed with If-None-Match failed");
+    assert_eq!(res.status_code, 200);
+    assert_ne!(res.headers.get("etag"), Some(&etag));
+}
+
+#[test]
+fn listen_args() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    // The environment is overridden by the arguments
+    let envs = [("FEEDLYNX_ADDRESS", "192.0.2.1"), ("FEEDLYNX_PORT", "1")];
+
+    let port = PORT + 16;
+    let listen = format!("127.0.0.1:{port}");
+    let child = start_server_with_args(feed_path.path(), port, &envs, &["--listen", &listen]);
+    fetch_feed(&listen);
+    drop(child);
+
+    // --port takes precedence over the port in --listen
+    let port = PORT + 17;
+    let _child = start_server_with_args(
+        feed_path.path(),
+        port,
+        &envs,
+        &["-l", "127.0.0.1:1", "-p", &port.to_string()],
+    );
+    fetch_feed(&format!("127.0.0.1:{port}"));
+}
+
+#[cfg(not(feature = "tls"))]
+#[test]
+fn tls_requires_feature() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let fixture = |name: &str| {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join(name)
+    };
+    let output = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+        ])
+        .env("FEEDLYNX_TLS_CERT", fixture("localhost.cert.pem"))
+        .env("FEEDLYNX_TLS_KEY", fixture("localhost.key.pem"))
+        .arg(feed_path.path())
+        .output()
+        .expect("failed to run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("tls feature"), "stderr: {stderr}");
+}
+
+/// The certificate is self-signed so this checks the server starts with it and only accepts TLS
+#[cfg(feature = "tls")]
+#[test]
+fn tls() {
+    use std::io::{BufRead, BufReader};
+
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let fixture = |name: &str| {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join(name)
+    };
+    let port = PORT + 28;
+    let mut child = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &port.to_string()),
+            ("FEEDLYNX_LOG", "info"),
+        ])
+        .env("FEEDLYNX_TLS_CERT", fixture("localhost.cert.pem"))
+        .env("FEEDLYNX_TLS_KEY", fixture("localhost.key.pem"))
+        .arg(feed_path.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map(StopOnDrop)
+        .expect("failed to spawn server");
+
+    let stderr = BufReader::new(child.0.stderr.take().unwrap());
+    let running = stderr
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.contains("HTTP server running on"))
+        .expect("server failed to start");
+    assert!(
+        running.ends_with(&format!("https://127.0.0.1:{port}")),
+        "{running}"
+    );
+
+    // Plain HTTP isn't answered
+    let res = minreq::get(format!("http://127.0.0.1:{}/version", port))
+        .with_timeout(2)
+        .send();
+    assert!(!res.is_ok_and(|res| res.status_code == 200));
+}
+
+#[test]
+fn json_logs() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 22;
+    let mut child = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &port.to_string()),
+            ("FEEDLYNX_LOG", "info"),
+            ("FEEDLYNX_LOG_FORMAT", "json"),
+        ])
+        .arg(feed_path.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn server");
+    let mut attempt = 0;
+    while minreq::get(format!("http://127.0.0.1:{}/", port))
+        .send()
+        .is_err()
+    {
+        attempt += 1;
+        assert!(attempt < 20, "server failed to start");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    child.kill().expect("failed to stop server");
+    let output = child.wait_with_output().expect("unable to read output");
+
+    let stderr = String::from_utf8(output.stderr).expect("logs are not UTF-8");
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert!(!lines.is_empty());
+    for line in lines {
+        let json: JsonValue = line.parse().expect("log line is not JSON");
+        let obj: &HashMap<_, _> = json.get().expect("log line is not an object");
+        for key in ["timestamp", "level", "target", "message"] {
+            assert!(obj[key].is_string(), "{key} missing from {line}");
+        }
+    }
+    assert!(stderr.contains(r#""message":"Creating initial feed at "#));
+}
+
+#[test]
+fn access_log_redacts_token() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 30;
+    let mut child = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &port.to_string()),
+            ("FEEDLYNX_LOG", "info"),
+            ("FEEDLYNX_ACCESS_LOG", "1"),
+        ])
+        .arg(feed_path.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn server");
+    let mut attempt = 0;
+    while minreq::get(format!("http://127.0.0.1:{}/", port))
+        .send()
+        .is_err()
+    {
+        attempt += 1;
+        assert!(attempt < 20, "server failed to start");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let res = minreq::get(format!(
+        "http://127.0.0.1:{port}/add?url=https%3A%2F%2Fexample.com%2F&token={PRIVATE_TOKEN}"
+    ))
+    .send()
+    .expect("GET /add failed");
+    assert_eq!(res.status_code, 200);
+    child.kill().expect("failed to stop server");
+    let output = child.wait_with_output().expect("unable to read output");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stderr
+        .lines()
+        .find(|line| line.contains("\"GET /add?"))
+        .unwrap_or_else(|| panic!("request not logged: {stderr}"));
+    assert!(
+        line.contains("/add?url=https%3A%2F%2Fexample.com%2F&token=REDACTED HTTP/1.1"),
+        "{line}"
+    );
+    assert!(!stderr.contains(PRIVATE_TOKEN), "{stderr}");
+}
+
+#[test]
+fn serve_command() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let port = PORT + 27;
+    let _child = start_server_with_args(feed_path.path(), port, &[], &["serve"]);
+    fetch_feed(&format!("127.0.0.1:{port}"));
+
+    // The feed path is required
+    let output = test_bin::get_test_bin("feedlynx")
+        .arg("serve")
+        .output()
+        .expect("failed to run serve");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("serve path/to/feed.xml"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn check() {
+    let sample_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("sample.xml");
+    let output = test_bin::get_test_bin("feedlynx")
+        .arg("check")
+        .arg(&sample_path)
+        .output()
+        .expect("failed to run check");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ok, 53 entries"), "stdout: {stdout}");
+
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    fs::write(feed_path.path(), "<feed>not a feed").expect("unable to write invalid feed");
+    let output = test_bin::get_test_bin("feedlynx")
+        .arg("check")
+        .arg(feed_path.path())
+        .output()
+        .expect("failed to run check");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unable to read feed"), "stderr: {stderr}");
+}
+
+#[test]
+fn repair_feed() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let corrupt_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml.corrupt")));
+    fs::write(feed_path.path(), "<feed>not a feed").expect("unable to write invalid feed");
+
+    // The server refuses to start by default
+    let output = test_bin::get_test_bin("feedlynx")
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &(PORT + 23).to_string()),
+        ])
+        .arg(feed_path.path())
+        .output()
+        .expect("failed to run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unable to read feed"), "stderr: {stderr}");
+
+    let port = PORT + 23;
+    let _child = start_server(
+        feed_path.path(),
+        port,
+        &[
+            ("FEEDLYNX_REPAIR_FEED", "1"),
+            ("FEEDLYNX_FEED_ID", "tag:example.com,2024:repaired"),
+        ],
+    );
+    let (feed, _) = fetch_feed(&format!("127.0.0.1:{}", port));
+    assert_eq!(feed.id(), "tag:example.com,2024:repaired");
+    assert!(feed.entries().is_empty());
+    assert_eq!(
+        fs::read_to_string(corrupt_path.path()).expect("unable to read corrupt feed"),
+        "<feed>not a feed"
+    );
+}
+
+#[test]
+fn trim_subcommand() {
+    let rand = base62::<8>();
+    let feed_path = std::env::temp_dir().join(format!("feed.{rand}.xml"));
+    let sample_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("sample.xml");
+    fs::copy(sample_path, &feed_path).expect("unable to copy sample feed");
+    let feed_path = RmOnDrop::new(feed_path);
+
+    let output = test_bin::get_test_bin("feedlynx")
+        .arg("trim")
+        .arg(feed_path.path())
+        .output()
+        .expect("failed to run trim");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("removed 3 entries"), "stdout: {stdout}");
+
+    let feed = feedlynx::Feed::read(feed_path.path()).expect("unable to read trimmed feed");
+    assert_eq!(feed.entries().len(), 50);
+}
+
+#[cfg(unix)]
+#[test]
+fn trim_signal() {
+    let rand = base62::<8>();
+    let feed_path = std::env::temp_dir().join(format!("feed.{rand}.xml"));
+    let sample_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("sample.xml");
+    fs::copy(sample_path, &feed_path).expect("unable to copy sample feed");
+    let feed_path = RmOnDrop::new(feed_path);
+
+    let port = PORT + 18;
+    let child = start_server(feed_path.path(), port, &[]);
+
+    let status = Command::new("kill")
+        .arg("-USR1")
+        .arg(child.0.id().to_string())
+        .status()
+        .expect("unable to run kill");
+    assert!(status.success());
+
+    // The feed is trimmed in the background, SIGUSR1 doesn't stop the server
+    let mut attempt = 0;
+    loop {
+        let feed = feedlynx::Feed::read(feed_path.path()).expect("unable to read feed");
+        if feed.entries().len() == 50 {
+            break;
+        }
+        attempt += 1;
+        assert!(attempt < 20, "feed was not trimmed");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let res = minreq::get(format!("http://127.0.0.1:{}/", port))
+        .send()
+        .expect("server stopped after SIGUSR1");
+    assert_eq!(res.status_code, 200);
+}
+
+#[test]
+fn import() {
+    let rand = base62::<8>();
+    let feed_path = RmOnDrop::new(std::env::temp_dir().join(format!("feed.{rand}.xml")));
+    let urls_path = RmOnDrop::new(std::env::temp_dir().join(format!("urls.{rand}.txt")));
+    feedlynx::Feed::generate_new(feed_path.path())
+        .save(&Default::default())
+        .expect("unable to save feed");
+
+    let port = serve_page("<html><head><title>Imported Page</title></head></html>");
+    let urls = format!(
+        "# Reading list\n\
+         http://127.0.0.1:{port}/one\n\
+         \n\
+         http://127.0.0.1:{port}/two\n\
+         http://127.0.0.1:{port}/one\n\
+         not a url\n"
+    );
+    fs::write(urls_path.path(), urls).expect("unable to write urls");
+
+    let output = test_bin::get_test_bin("feedlynx")
+        .arg("import")
+        .arg(feed_path.path())
+        .arg(urls_path.path())
+        .env("FEEDLYNX_ALLOW_PRIVATE_HOSTS", "1")
+        .output()
+        .expect("failed to run import");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("added 2, skipped 1, failed 1"),
+        "stdout: {stdout}"
+    );
+
+    let feed = feedlynx::Feed::read(feed_path.path()).expect("unable to read feed");
+    assert_eq!(feed.entries().len(), 2);
+    assert_eq!(feed.entries()[0].title().as_str(), "Imported Page");
+}
+
+/// Serve `html` in response to every request on a random port, returning the port
+fn serve_page(html: &'static str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("unable to bind");
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{html}",
+                html.len()
+            );
+        }
+    });
+    port
+}
+
+/// Spawn the server and wait for it to accept requests
+fn start_server(feed_path: &Path, port: u16, envs: &[(&str, &str)]) -> StopOnDrop {
+    start_server_with_args(feed_path, port, envs, &[])
+}
+
+/// Spawn the server with extra command line arguments and wait for it to accept requests
+fn start_server_with_args(
+    feed_path: &Path,
+    port: u16,
+    envs: &[(&str, &str)],
+    args: &[&str],
+) -> StopOnDrop {
+    let mut binary = test_bin::get_test_bin("feedlynx");
+    binary
+        .envs([
+            ("FEEDLYNX_PRIVATE_TOKEN", PRIVATE_TOKEN),
+            ("FEEDLYNX_FEED_TOKEN", FEED_TOKEN),
+            ("FEEDLYNX_PORT", &port.to_string()),
+            ("FEEDLYNX_LOG", "debug"),
+        ])
+        .envs(envs.iter().copied())
+        .args(args)
+        .arg(feed_path);
+    let mut child = binary
+        .spawn()
+        .map(StopOnDrop)
+        .expect("failed to spawn server");
+
+    let mut attempt = 0;
+    loop {
+        if let Some(code) = child.0.try_wait().expect("unable to get status") {
+            panic!("server failed to start ({})", code)
+        }
+        match minreq::get(format!("http://127.0.0.1:{}/", port)).send() {
+            Ok(_) => return child,
+            Err(err) => {
+                attempt += 1;
+                if attempt > 10 {
+                    panic!("GET / failed: {err}");
+                }
+                std::thread::sleep(Duration::from_millis(250));
+            }
+        }
+    }
+}
+
+/// Run a server in this process on a free port, returning it and the address it's listening on
+fn spawn_server(feed_path: &Path) -> (RunningServer, String) {
+    Feed::generate_new(feed_path)
+        .save(&SaveOptions::default())
+        .expect("unable to create feed");
+    let server = ServerBuilder::new(feed_path)
+        .listen("127.0.0.1", 0)
+        .private_token(PrivateToken(PRIVATE_TOKEN.to_string()))
+        .feed_token(FeedToken(FEED_TOKEN.to_string()))
+        .build()
+        .expect("unable to create server")
+        .spawn()
+        .expect("unable to spawn server");
+    let address = server.server().server_addrs()[0].to_string();
+    (server, address)
+}
+
 fn fetch_feed(address: &str) -> (atom::Feed, String) {
     let res = minreq::get(format!("http://{}/feed/{}", address, FEED_TOKEN))
         .send()
@@ -297,7 +2238,7 @@ fn fetch_feed(address: &str) -> (atom::Feed, String) {
         .headers
         .get("content-type")
         .expect("Content-Type header is set");
-    assert_eq!(content_type, "application/atom+xml");
+    assert_eq!(content_type, "application/atom+xml; charset=utf-8");
 
     // Get the Last-Modified header
     let last_modified = res